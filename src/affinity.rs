@@ -0,0 +1,136 @@
+//! 线程与 CPU 核心的绑定，用于在相同的核心分布下对比各个服务器实现
+
+use nix::sched::{sched_setaffinity, CpuSet};
+use nix::unistd::Pid;
+
+use crate::tp::ThreadPoolBuilder;
+
+use std::collections::HashSet;
+use std::fs;
+use std::io;
+
+/// 将当前线程绑定到编号为 core 的 CPU 核心
+pub fn pin_current_thread(core: usize) -> nix::Result<()> {
+    let mut set = CpuSet::new();
+    set.set(core)?;
+    sched_setaffinity(Pid::from_raw(0), &set)
+}
+
+/// 解析 `0-3,8,10-11` 形式的核心列表，与 `/sys` 中 cpulist 的格式相同。
+/// 重复的核心只保留第一次出现的位置，列表为空时返回错误
+pub fn parse_core_list(list: &str) -> Result<Vec<usize>, String> {
+    let mut cores = vec![];
+    for part in list.trim().split(',').filter(|part| !part.is_empty()) {
+        let parse = |s: &str| {
+            s.trim()
+                .parse::<usize>()
+                .map_err(|_| format!("invalid core id `{}` in `{}`", s, list))
+        };
+        match part.split_once('-') {
+            Some((start, end)) => {
+                let (start, end) = (parse(start)?, parse(end)?);
+                if start > end {
+                    return Err(format!("invalid core range `{}`", part));
+                }
+                cores.extend(start..=end);
+            }
+            None => cores.push(parse(part)?),
+        }
+    }
+    if cores.is_empty() {
+        return Err(format!("empty core list `{}`", list.trim()));
+    }
+    let mut seen = HashSet::new();
+    cores.retain(|core| seen.insert(*core));
+    Ok(cores)
+}
+
+/// 读取 NUMA 节点上的 CPU 核心列表
+pub fn numa_node_cores(node: usize) -> io::Result<Vec<usize>> {
+    let path = format!("/sys/devices/system/node/node{}/cpulist", node);
+    let list = fs::read_to_string(path)?;
+    parse_core_list(&list).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+/// 服务器的绑核参数
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AffinityArgs {
+    /// 工作线程依次绑定的核心
+    pub worker_cores: Option<Vec<usize>>,
+    /// 事件循环线程绑定的核心
+    pub reactor_core: Option<usize>,
+}
+
+impl AffinityArgs {
+    /// 设置一个参数，key 不带 `--` 前缀，未知参数返回 `Ok(false)`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "worker-cores" => self.worker_cores = Some(parse_core_list(value)?),
+            "numa-node" => {
+                let node = value
+                    .parse()
+                    .map_err(|_| format!("invalid NUMA node `{}`", value))?;
+                let cores = numa_node_cores(node)
+                    .map_err(|err| format!("fail to read cores of NUMA node {}: {}", node, err))?;
+                self.worker_cores = Some(cores);
+            }
+            "reactor-core" => {
+                let core = value
+                    .parse()
+                    .map_err(|_| format!("invalid core id `{}`", value))?;
+                self.reactor_core = Some(core);
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    /// 按照参数为线程池配置绑核
+    pub fn configure(&self, builder: ThreadPoolBuilder) -> ThreadPoolBuilder {
+        match &self.worker_cores {
+            Some(cores) => builder.core_ids(cores.iter().copied()),
+            None => builder,
+        }
+    }
+
+    /// 将当前线程（事件循环线程）绑定到指定核心
+    pub fn pin_reactor(&self) {
+        if let Some(core) = self.reactor_core {
+            match pin_current_thread(core) {
+                Ok(()) => println!("Event loop pinned to core {}", core),
+                Err(err) => eprintln!("[Error] Fail to pin event loop to core {}: {}", core, err),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse_core_list;
+
+    #[test]
+    fn parses_ranges_and_single_cores() {
+        assert_eq!(parse_core_list("0-3,8,10-11\n").unwrap(), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert_eq!(parse_core_list(" 5 ").unwrap(), vec![5]);
+        assert_eq!(parse_core_list("2-2").unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn duplicates_keep_first_position() {
+        assert_eq!(parse_core_list("3,0-3,1").unwrap(), vec![3, 0, 1, 2]);
+    }
+
+    #[test]
+    fn empty_list_is_rejected() {
+        for list in ["", "\n", ","] {
+            assert!(parse_core_list(list).is_err(), "{:?} should be rejected", list);
+        }
+    }
+
+    #[test]
+    fn invalid_lists_are_rejected() {
+        for list in ["a", "1-", "-1", "3-1", "1-2-3", "0,x"] {
+            assert!(parse_core_list(list).is_err(), "{:?} should be rejected", list);
+        }
+    }
+}
@@ -0,0 +1,41 @@
+//! 聊天服务器的 I/O 后端，每个后端以不同的方式处理连接与读写，
+//! 消息的处理统一交给 `ChatServer`。
+//! 除阻塞 I/O 的线程池后端外，发给连接的数据先放入有上限的发送队列（`outbound`），在 socket 可写时写出
+
+use crate::chat::ChatServer;
+use crate::tp::ThreadPoolError;
+
+use std::io;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+
+mod epoll;
+mod iouring;
+mod mio_poll;
+mod outbound;
+mod threadpool;
+mod tokio_rt;
+
+pub use epoll::EpollBackend;
+pub use iouring::IoUringBackend;
+pub use mio_poll::MioBackend;
+pub use outbound::{OutboundConfig, OutboundStats, SlowConsumerPolicy, DEFAULT_CAPACITY, DEFAULT_PAUSE_TIMEOUT};
+pub use threadpool::ThreadPoolBackend;
+pub use tokio_rt::TokioBackend;
+
+/// 服务器的 I/O 后端
+pub trait Backend {
+    /// 在当前线程上运行服务器，直到发生无法恢复的错误
+    fn run(self, server: ChatServer) -> io::Result<()>;
+}
+
+/// 在多个线程间共享的服务器核心
+type SharedServer = Arc<Mutex<ChatServer>>;
+
+/// 获取锁，忽略其他线程 panic 导致的中毒状态
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn pool_error(err: ThreadPoolError) -> io::Error {
+    io::Error::other(err)
+}
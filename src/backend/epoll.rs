@@ -0,0 +1,309 @@
+use nix::errno::Errno;
+use nix::sys::epoll::*;
+use nix::sys::socket::*;
+use nix::unistd::{close, read, write};
+
+use std::io::{self, Write};
+use std::net::SocketAddr;
+use std::os::unix::io::RawFd;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::chat::{Action, ChatServer, ConnId};
+use crate::tp::{SharedQueueThreadPool, ThreadPool, ThreadPoolBuilder, TimerWheel};
+
+use super::outbound::{Flushed, OutboundConfig, Outbox, Push, EXPIRE_INTERVAL};
+use super::{lock, pool_error, Backend};
+
+const MAX_EVENTS: usize = 128;
+const BUFFER_SIZE: usize = 1024;
+// 打印线程池统计信息的间隔
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 服务器核心与发送队列，转发时需要同时访问两者
+struct State {
+    server: ChatServer,
+    outbox: Outbox,
+}
+
+type SharedState = Arc<Mutex<State>>;
+
+/// 事件循环使用的两个 epoll 实例。
+/// 连接的可读事件以单次触发的方式注册在 epoll_fd 中，同一时间只有一个线程读取；
+/// 可写事件注册在 write_fd 中，write_fd 本身注册在 epoll_fd 中，由事件循环线程写出发送队列
+#[derive(Debug, Clone, Copy)]
+struct Poller {
+    epoll_fd: RawFd,
+    write_fd: RawFd,
+}
+
+/// epoll 事件循环加线程池：事件循环接收连接并等待可读事件，
+/// 读取与转发交给线程池完成，写不完的数据在 socket 可写时由事件循环写出
+pub struct EpollBackend {
+    addr: SocketAddr,
+    pool: ThreadPoolBuilder,
+    buffer_size: usize,
+    outbound: OutboundConfig,
+}
+
+impl EpollBackend {
+    pub fn new(addr: SocketAddr, pool: ThreadPoolBuilder) -> Self {
+        Self {
+            addr,
+            pool,
+            buffer_size: BUFFER_SIZE,
+            outbound: OutboundConfig::default(),
+        }
+    }
+
+    /// 每个连接的发送队列的上限与慢消费者的处理方式
+    pub fn outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// 每次读取的缓冲区大小
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+}
+
+impl Backend for EpollBackend {
+    fn run(self, server: ChatServer) -> io::Result<()> {
+        // 创建一个非阻塞的 TCP socket 并监听
+        let listen_fd = socket(
+            AddressFamily::Inet,
+            SockType::Stream,
+            SockFlag::SOCK_NONBLOCK,
+            SockProtocol::Tcp,
+        )?;
+        setsockopt(listen_fd, sockopt::ReuseAddr, &true)?;
+        bind(listen_fd, &SockAddr::new_inet(InetAddr::from_std(&self.addr)))?;
+        listen(listen_fd, 1024)?;
+        println!("Server listen on {}", self.addr);
+
+        let tp: SharedQueueThreadPool = self.pool.build().map_err(pool_error)?;
+        let tp = Arc::new(tp);
+        let state: SharedState = Arc::new(Mutex::new(State {
+            server,
+            outbox: Outbox::new(self.outbound),
+        }));
+
+        // 定时器由事件循环驱动，epoll_wait 最多等待到最早的定时器到期
+        let timers = TimerWheel::new(Arc::clone(&tp), Duration::from_millis(100), 512);
+        {
+            let tp = Arc::clone(&tp);
+            timers.schedule_every(STATS_INTERVAL, move || println!("[Stats] {}", tp.stats()));
+        }
+
+        let poller = Poller {
+            epoll_fd: epoll_create1(EpollCreateFlags::empty())?,
+            write_fd: epoll_create1(EpollCreateFlags::empty())?,
+        };
+        {
+            // 断开让生产者暂停过久的慢消费者
+            let state = Arc::clone(&state);
+            timers.schedule_every(EXPIRE_INTERVAL, move || {
+                // 持有锁直到关闭完成，保证文件描述符不会在此期间被关闭并复用
+                let mut state = lock(&state);
+                for id in state.outbox.expire(Instant::now()) {
+                    shutdown_connection(&mut state, poller, id as RawFd);
+                }
+            });
+        }
+        // 监听 socket 与 write_fd 使用水平触发，每次尽可能接收所有连接、写出所有可写的连接
+        let mut listen_event = EpollEvent::new(EpollFlags::EPOLLIN, listen_fd as u64);
+        epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlAdd, listen_fd, &mut listen_event)?;
+        let mut write_event = EpollEvent::new(EpollFlags::EPOLLIN, poller.write_fd as u64);
+        epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlAdd, poller.write_fd, &mut write_event)?;
+
+        let mut events = [EpollEvent::empty(); MAX_EVENTS];
+        loop {
+            let timeout = timers
+                .next_timeout(Instant::now())
+                .map_or(-1, |timeout| timeout.as_micros().div_ceil(1000) as isize);
+            let num_events = match epoll_wait(poller.epoll_fd, &mut events, timeout) {
+                Ok(n) => n,
+                Err(Errno::EINTR) => continue,
+                Err(err) => return Err(err.into()),
+            };
+            timers.advance(Instant::now());
+
+            for event in &events[..num_events] {
+                let fd = event.data() as RawFd;
+                if fd == listen_fd {
+                    accept_all(listen_fd, poller, &state);
+                } else if fd == poller.write_fd {
+                    flush_writable(poller, &state);
+                } else {
+                    // 连接使用 EPOLLONESHOT 注册，同一时间只有一个线程读取
+                    let state = Arc::clone(&state);
+                    let buffer_size = self.buffer_size;
+                    tp.spawn(move || serve(fd, poller, buffer_size, &state));
+                }
+            }
+        }
+    }
+}
+
+/// 接收所有排队中的连接，并以单次触发的方式注册可读事件
+fn accept_all(listen_fd: RawFd, poller: Poller, state: &SharedState) {
+    loop {
+        let fd = match accept4(listen_fd, SockFlag::SOCK_NONBLOCK) {
+            Ok(fd) => fd,
+            Err(Errno::EAGAIN) => return,
+            Err(Errno::EINTR) => continue,
+            Err(err) => {
+                eprintln!("Fail to accept connection: {}", err);
+                return;
+            }
+        };
+        let addr = match getpeername(fd) {
+            Ok(SockAddr::Inet(addr)) => addr.to_std(),
+            _ => {
+                let _ = close(fd);
+                continue;
+            }
+        };
+
+        let mut core = lock(state);
+        if core.server.is_full() {
+            println!("Reject {}: too many connections", addr);
+            let _ = close(fd);
+            continue;
+        }
+        // 可写事件先以不关注任何事件的方式注册，队列写不完时再修改
+        let mut unarmed = EpollEvent::new(EpollFlags::EPOLLONESHOT, fd as u64);
+        let registered = epoll_ctl(poller.write_fd, EpollOp::EpollCtlAdd, fd, &mut unarmed)
+            .and_then(|_| epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlAdd, fd, &mut readable(fd)));
+        if let Err(err) = registered {
+            eprintln!("Fail to register {}: {}", addr, err);
+            let _ = close(fd);
+            continue;
+        }
+        core.outbox.open(fd as ConnId);
+        let actions = core.server.connect(fd as ConnId, addr);
+        deliver(&mut core, poller, None, actions);
+    }
+}
+
+/// 在线程池中读取连接直到数据读完，然后重新注册可读事件。
+/// 连接向慢消费者发送消息而被暂停时不再注册，慢消费者的队列变短后由 `flush` 重新注册
+fn serve(fd: RawFd, poller: Poller, buffer_size: usize, state: &SharedState) {
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match read(fd, &mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let mut state = lock(state);
+                let actions = state.server.receive(fd as ConnId, &buf[..n]);
+                deliver(&mut state, poller, Some(fd as ConnId), actions);
+                if state.outbox.is_paused(fd as ConnId) {
+                    return;
+                }
+            }
+            Err(Errno::EAGAIN) => {
+                if epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlMod, fd, &mut readable(fd)).is_ok() {
+                    return;
+                }
+                break;
+            }
+            Err(Errno::EINTR) => continue,
+            Err(_) => break,
+        }
+    }
+    close_connection(fd, poller, &mut lock(state));
+}
+
+/// 写出所有可写的连接的发送队列
+fn flush_writable(poller: Poller, state: &SharedState) {
+    let mut events = [EpollEvent::empty(); MAX_EVENTS];
+    let num_events = epoll_wait(poller.write_fd, &mut events, 0).unwrap_or(0);
+    let mut state = lock(state);
+    for event in &events[..num_events] {
+        flush(&mut state, poller, event.data() as RawFd);
+    }
+}
+
+fn readable(fd: RawFd) -> EpollEvent {
+    EpollEvent::new(EpollFlags::EPOLLIN | EpollFlags::EPOLLONESHOT, fd as u64)
+}
+
+fn writable(fd: RawFd) -> EpollEvent {
+    EpollEvent::new(EpollFlags::EPOLLOUT | EpollFlags::EPOLLONESHOT, fd as u64)
+}
+
+/// 关闭连接，调用者需要持有服务器的锁，保证文件描述符不会在转发时被复用
+fn close_connection(fd: RawFd, poller: Poller, state: &mut State) {
+    let actions = state.server.disconnect(fd as ConnId);
+    let resumed = state.outbox.remove(fd as ConnId);
+    let _ = epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlDel, fd, None);
+    let _ = epoll_ctl(poller.write_fd, EpollOp::EpollCtlDel, fd, None);
+    let _ = close(fd);
+    resume(poller, resumed);
+    deliver(state, poller, None, actions);
+}
+
+/// 把数据放入发送队列并尽量写出，producer 为正在读取的连接。
+/// 调用者需要持有服务器的锁
+fn deliver(state: &mut State, poller: Poller, producer: Option<ConnId>, actions: Vec<Action>) {
+    for action in actions {
+        match action {
+            Action::Send { to, data } => match state.outbox.push(to, data, producer) {
+                Push::Queued | Push::Pause => flush(state, poller, to as RawFd),
+                Push::Disconnect => shutdown_connection(state, poller, to as RawFd),
+            },
+            // 关闭后连接变为可读，读到连接结束后照常关闭
+            Action::Close { id } => {
+                if state.outbox.close(id) {
+                    shutdown_connection(state, poller, id as RawFd);
+                }
+            }
+        }
+    }
+}
+
+/// 以非阻塞方式写出连接的发送队列，写不完时等待可写事件
+fn flush(state: &mut State, poller: Poller, fd: RawFd) {
+    match state.outbox.flush(fd as ConnId, &mut FdWriter(fd)) {
+        Ok(Flushed::Pending) => {
+            let _ = epoll_ctl(poller.write_fd, EpollOp::EpollCtlMod, fd, &mut writable(fd));
+        }
+        Ok(Flushed::Drained) => (),
+        // 写入出错时关闭连接，读取的线程随后会断开连接
+        Ok(Flushed::Close) | Err(_) => shutdown_connection(state, poller, fd),
+    }
+    let resumed = state.outbox.resume(fd as ConnId);
+    resume(poller, resumed);
+}
+
+/// 关闭连接的读写，读取的线程读到连接结束后照常断开连接。
+/// 连接被暂停读取时重新注册可读事件，否则没有线程会读到连接结束。调用者需要持有服务器的锁
+fn shutdown_connection(state: &mut State, poller: Poller, fd: RawFd) {
+    let _ = shutdown(fd, Shutdown::Both);
+    if state.outbox.shutdown(fd as ConnId) {
+        resume(poller, vec![fd as ConnId]);
+    }
+}
+
+/// 重新注册被暂停的连接的可读事件
+fn resume(poller: Poller, producers: Vec<ConnId>) {
+    for producer in producers {
+        let fd = producer as RawFd;
+        let _ = epoll_ctl(poller.epoll_fd, EpollOp::EpollCtlMod, fd, &mut readable(fd));
+    }
+}
+
+/// 以 `Write` 的方式写入非阻塞的文件描述符
+struct FdWriter(RawFd);
+
+impl Write for FdWriter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        write(self.0, buf).map_err(io::Error::from)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
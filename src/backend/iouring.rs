@@ -0,0 +1,459 @@
+use io_uring::{opcode, squeue, types, IoUring, SubmissionQueue};
+use nix::sys::socket::{getpeername, SockAddr};
+use slab::Slab;
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io;
+use std::net::{SocketAddr, TcpListener};
+use std::os::unix::io::{AsRawFd, RawFd};
+use std::ptr;
+use std::sync::Arc;
+use std::time::Instant;
+
+use crate::chat::{Action, ChatServer, ConnId};
+
+use super::outbound::{OutboundConfig, Outbox, Push, EXPIRE_INTERVAL};
+use super::Backend;
+
+const BUFFER_SIZE: usize = 2048;
+
+#[derive(Clone, Debug)]
+enum Token {
+    Accept,
+    Poll {
+        fd: RawFd,
+    },
+    Read {
+        fd: RawFd,
+        buf_index: usize,
+    },
+    Write {
+        fd: RawFd,
+        data: Arc<[u8]>,
+        offset: usize,
+    },
+    /// 有连接被暂停读取时定期检查慢消费者
+    Expire,
+}
+
+struct AcceptCount {
+    entry: squeue::Entry,
+    count: usize,
+}
+
+impl AcceptCount {
+    /// 新建 AcceptCount 结构体,fd 表示监听的文件描述符,token 表示 sqe 携带的用户数据
+    /// count 表示该文件描述符所能接收到的最大连接
+    fn new(fd: RawFd, token: usize, count: usize) -> Self {
+        Self {
+            entry: opcode::Accept::new(types::Fd(fd), ptr::null_mut(), ptr::null_mut())
+                .build()
+                .user_data(token as _),
+            count,
+        }
+    }
+
+    /// 向提交队列中提交事件
+    fn push_to(&mut self, sq: &mut SubmissionQueue<'_>) {
+        while self.count > 0 {
+            unsafe {
+                match sq.push(&self.entry) {
+                    Ok(_) => self.count -= 1,
+                    Err(_) => break,
+                }
+            }
+        }
+        sq.sync();
+    }
+}
+
+/// io_uring 事件循环：单线程提交接收、轮询、读取和发送请求，
+/// 每个连接同一时间只有一个发送请求，其余的数据在发送队列中排队
+pub struct IoUringBackend {
+    addr: SocketAddr,
+    ring_entries: u32,
+    buffer_size: usize,
+    outbound: OutboundConfig,
+}
+
+impl IoUringBackend {
+    /// ring_entries 为提交队列的深度
+    pub fn new(addr: SocketAddr, ring_entries: u32) -> Self {
+        Self {
+            addr,
+            ring_entries,
+            buffer_size: BUFFER_SIZE,
+            outbound: OutboundConfig::default(),
+        }
+    }
+
+    /// 每个连接的发送队列的上限与慢消费者的处理方式
+    pub fn outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// 每次读取的缓冲区大小
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+}
+
+/// 事件循环的状态
+struct Ring {
+    /// 用于存放提交失败的事件
+    backlog: VecDeque<squeue::Entry>,
+    /// 用于存放空闲的缓冲区的 buf_index,一般为关闭连接的socket被回收的
+    bufpool: Vec<usize>,
+    /// 用来存储内存中的缓冲区的指针，使用 buf_index 进行访问
+    buf_alloc: Slab<Box<[u8]>>,
+    /// 一段用来存放不同事件token的内存区域，通过token_index获取到事件类型及信息
+    token_alloc: Slab<Token>,
+    /// 每个连接的发送队列
+    outbox: Outbox,
+    /// 有发送事件尚未完成的连接
+    writing: HashSet<RawFd>,
+    /// 已经断开、等待发送事件返回后再关闭 fd 的连接
+    closing: HashSet<RawFd>,
+    /// 被暂停读取的连接与它的轮询事件的 token
+    paused: HashMap<RawFd, usize>,
+    /// 定时事件的 token 与时长，时长在事件返回前必须保持有效
+    expire_token: usize,
+    expire_after: Box<types::Timespec>,
+    /// 是否有尚未返回的定时事件
+    expiring: bool,
+}
+
+impl Ring {
+    /// 向提交队列中放入事件，队列已满时放入 backlog 等待下一次提交
+    fn push(&mut self, sq: &mut SubmissionQueue<'_>, entry: squeue::Entry) {
+        unsafe {
+            if sq.push(&entry).is_err() {
+                self.backlog.push_back(entry);
+            }
+        }
+    }
+
+    /// 注册轮询事件，等待 socket 可读
+    fn poll(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd, token_index: usize) {
+        let entry = opcode::PollAdd::new(types::Fd(fd), libc::POLLIN as _)
+            .build()
+            .user_data(token_index as _);
+        self.push(sq, entry);
+    }
+
+    /// 注册发送事件，token 持有数据直到发送完成
+    fn send(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd, data: Arc<[u8]>, offset: usize) {
+        let buf = &data[offset..];
+        let entry = opcode::Send::new(types::Fd(fd), buf.as_ptr(), buf.len() as _).build();
+        let token_index = self.token_alloc.insert(Token::Write { fd, data, offset });
+        self.push(sq, entry.user_data(token_index as _));
+    }
+
+    /// 把数据放入发送队列，producer 为正在读取的连接
+    fn deliver(&mut self, sq: &mut SubmissionQueue<'_>, producer: Option<RawFd>, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Send { to, data } => match self.outbox.push(to, data, producer.map(|fd| fd as ConnId)) {
+                    Push::Queued | Push::Pause => self.write_next(sq, to as RawFd),
+                    Push::Disconnect => self.shutdown(sq, to as RawFd),
+                },
+                // 关闭后轮询的读取事件会读到连接结束，照常关闭
+                // 等连接上的发送全部完成后再关闭
+                Action::Close { id } => {
+                    let fd = id as RawFd;
+                    if self.outbox.close(id) && !self.writing.contains(&fd) {
+                        self.shutdown(sq, fd);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 连接没有正在进行的发送事件时，取出发送队列中的下一帧发送
+    fn write_next(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd) {
+        if !self.writing.contains(&fd) {
+            if let Some(data) = self.outbox.pop(fd as ConnId) {
+                self.writing.insert(fd);
+                self.send(sq, fd, data, 0);
+            }
+        }
+        for producer in self.outbox.resume(fd as ConnId) {
+            self.resume(sq, producer as RawFd);
+        }
+    }
+
+    /// 一帧发送完成，继续发送下一帧，队列已空且连接等待关闭时关闭连接
+    fn write_done(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd) {
+        self.writing.remove(&fd);
+        self.write_next(sq, fd);
+        if !self.writing.contains(&fd) && self.outbox.is_closing(fd as ConnId) {
+            self.shutdown(sq, fd);
+        }
+    }
+
+    /// 关闭连接的读写，轮询的读取事件随后会读到连接结束，照常关闭连接。
+    /// 连接被暂停读取时没有轮询事件，需要恢复轮询
+    fn shutdown(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd) {
+        shutdown(fd);
+        if self.outbox.shutdown(fd as ConnId) {
+            self.resume(sq, fd);
+        }
+    }
+
+    /// 暂停读取连接，保留轮询事件的 token 直到恢复
+    fn pause(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd, token_index: usize) {
+        self.token_alloc[token_index] = Token::Poll { fd };
+        self.paused.insert(fd, token_index);
+        if !self.expiring {
+            self.expiring = true;
+            let entry = opcode::Timeout::new(&*self.expire_after).build();
+            self.push(sq, entry.user_data(self.expire_token as _));
+        }
+    }
+
+    /// 定时事件返回，断开让生产者暂停过久的慢消费者，仍有连接被暂停时继续定时
+    fn expire(&mut self, sq: &mut SubmissionQueue<'_>) {
+        self.expiring = false;
+        for id in self.outbox.expire(Instant::now()) {
+            self.shutdown(sq, id as RawFd);
+        }
+        if let Some((&fd, &token_index)) = self.paused.iter().next() {
+            self.pause(sq, fd, token_index);
+        }
+    }
+
+    /// 恢复读取被暂停的连接
+    fn resume(&mut self, sq: &mut SubmissionQueue<'_>, fd: RawFd) {
+        if let Some(token_index) = self.paused.remove(&fd) {
+            self.poll(sq, fd, token_index);
+        }
+    }
+
+    /// 关闭连接并回收 token。
+    /// 内核中还有发送事件时 fd 不能立即关闭，否则事件返回时 fd 可能已经属于新的连接，
+    /// 此时先关闭读写让发送事件尽快返回，由 `sent` 关闭 fd
+    fn close(&mut self, sq: &mut SubmissionQueue<'_>, server: &mut ChatServer, fd: RawFd, token_index: usize) {
+        self.token_alloc.remove(token_index);
+        let actions = server.disconnect(fd as ConnId);
+        for producer in self.outbox.remove(fd as ConnId) {
+            self.resume(sq, producer as RawFd);
+        }
+        if self.writing.remove(&fd) {
+            shutdown(fd);
+            self.closing.insert(fd);
+        } else {
+            unsafe {
+                libc::close(fd);
+            }
+        }
+        self.deliver(sq, None, actions);
+    }
+
+    /// 发送事件返回并回收 token，连接已经断开时关闭 fd 并返回 `false`
+    fn sent(&mut self, fd: RawFd, token_index: usize) -> bool {
+        self.token_alloc.remove(token_index);
+        if !self.closing.remove(&fd) {
+            return true;
+        }
+        unsafe {
+            libc::close(fd);
+        }
+        false
+    }
+}
+
+impl Backend for IoUringBackend {
+    fn run(self, mut server: ChatServer) -> io::Result<()> {
+        let mut ring = IoUring::new(self.ring_entries)?;
+        let listener = TcpListener::bind(self.addr)?;
+        println!("Server listen on {}", listener.local_addr()?);
+
+        let mut token_alloc = Slab::with_capacity(64);
+        let expire_token = token_alloc.insert(Token::Expire);
+        let mut state = Ring {
+            backlog: VecDeque::new(),
+            bufpool: Vec::with_capacity(64),
+            buf_alloc: Slab::with_capacity(64),
+            token_alloc,
+            outbox: Outbox::new(self.outbound),
+            writing: HashSet::new(),
+            closing: HashSet::new(),
+            paused: HashMap::new(),
+            expire_token,
+            expire_after: Box::new(types::Timespec::new().sec(EXPIRE_INTERVAL.as_secs())),
+            expiring: false,
+        };
+
+        // 从 io_uring 实例中获取提交者,提交队列，完成队列
+        let (submitter, mut sq, mut cq) = ring.split();
+
+        // 建立 AcceptCount，用于计算监听的文件描述符并提交事件
+        let mut accept = AcceptCount::new(listener.as_raw_fd(), state.token_alloc.insert(Token::Accept), 10);
+        accept.push_to(&mut sq);
+
+        loop {
+            // 提交SQ里的所有队列，等待至少一个事件成功返回
+            match submitter.submit_and_wait(1) {
+                Ok(_) => (),
+                Err(ref err) if err.raw_os_error() == Some(libc::EBUSY) => (),
+                Err(err) => return Err(err),
+            }
+            // 同步完成队列，刷新在内核中的CQEs
+            cq.sync();
+
+            loop {
+                if sq.is_full() {
+                    // 提交队列满了的时候提交所有任务到内核
+                    match submitter.submit() {
+                        Ok(_) => (),
+                        Err(ref err) if err.raw_os_error() == Some(libc::EBUSY) => break,
+                        Err(err) => return Err(err),
+                    }
+                }
+                // 同步提交队列的内容
+                sq.sync();
+
+                match state.backlog.pop_front() {
+                    Some(sqe) => unsafe {
+                        // 向SQ中提交事件（此时没有被提交到内核中）
+                        let _ = sq.push(&sqe);
+                    },
+                    None => break,
+                }
+            }
+
+            accept.push_to(&mut sq);
+
+            for cqe in &mut cq {
+                // 获取 CQE 的结果与用户数据（用于判断是什么事件）
+                let ret = cqe.result();
+                let token_index = cqe.user_data() as usize;
+                let token = match state.token_alloc.get(token_index) {
+                    Some(token) => token.clone(),
+                    None => continue,
+                };
+
+                // 定时事件到期时返回 -ETIME
+                if let Token::Expire = token {
+                    state.expire(&mut sq);
+                    continue;
+                }
+
+                if ret < 0 {
+                    // 表明该事件执行失败了
+                    eprintln!("token {:?} error: {:?}", token, io::Error::from_raw_os_error(-ret));
+                    match token {
+                        Token::Accept => accept.count += 1,
+                        Token::Expire => unreachable!(),
+                        Token::Poll { fd } => state.close(&mut sq, &mut server, fd, token_index),
+                        Token::Read { fd, buf_index } => {
+                            state.bufpool.push(buf_index);
+                            state.close(&mut sq, &mut server, fd, token_index);
+                        }
+                        // 连接已经无法写入，关闭后读取事件会照常关闭连接
+                        Token::Write { fd, .. } => {
+                            if state.sent(fd, token_index) {
+                                state.writing.remove(&fd);
+                                state.shutdown(&mut sq, fd);
+                            }
+                        }
+                    }
+                    continue;
+                }
+
+                match token {
+                    Token::Accept => {
+                        // 当接收到客户端连接时，将 accept 的 count 域进行迭代
+                        accept.count += 1;
+                        // 此时收到的结果是一个文件描述符，表示的是接收到连接的socket
+                        let fd = ret;
+                        let addr = match getpeername(fd) {
+                            Ok(SockAddr::Inet(addr)) => addr.to_std(),
+                            _ => {
+                                unsafe {
+                                    libc::close(fd);
+                                }
+                                continue;
+                            }
+                        };
+                        if server.is_full() {
+                            println!("Reject {}: too many connections", addr);
+                            unsafe {
+                                libc::close(fd);
+                            }
+                            continue;
+                        }
+                        state.outbox.open(fd as ConnId);
+                        let actions = server.connect(fd as ConnId, addr);
+                        state.deliver(&mut sq, None, actions);
+                        // 创建poll实例，不断轮询检测是否从该socket中收到信息
+                        let poll_token = state.token_alloc.insert(Token::Poll { fd });
+                        state.poll(&mut sq, fd, poll_token);
+                    }
+
+                    Token::Poll { fd } => {
+                        let buf_index = match state.bufpool.pop() {
+                            Some(buf_index) => buf_index,
+                            None => state.buf_alloc.insert(vec![0u8; self.buffer_size].into_boxed_slice()),
+                        };
+                        let buf = &mut state.buf_alloc[buf_index];
+
+                        // 当 Poll 事件返回后表明有一个可读事件发生，此时应当注册读取事件
+                        let read_e = opcode::Recv::new(types::Fd(fd), buf.as_mut_ptr(), buf.len() as _)
+                            .build()
+                            .user_data(token_index as _);
+                        state.token_alloc[token_index] = Token::Read { fd, buf_index };
+                        state.push(&mut sq, read_e);
+                    }
+
+                    Token::Read { fd, buf_index } => {
+                        // 读取完成后缓冲区就可以用于下一次读取
+                        state.bufpool.push(buf_index);
+                        if ret == 0 {
+                            // 结果为0,表明对方关闭了连接
+                            state.close(&mut sq, &mut server, fd, token_index);
+                        } else {
+                            // 读取成功，此时的结果表明读取的字节数
+                            let len = ret as usize;
+                            let actions = server.receive(fd as ConnId, &state.buf_alloc[buf_index][..len]);
+                            state.deliver(&mut sq, Some(fd), actions);
+                            if state.outbox.is_paused(fd as ConnId) {
+                                // 向慢消费者发送了消息，等它的队列变短后再继续轮询
+                                state.pause(&mut sq, fd, token_index);
+                            } else {
+                                // 继续轮询 socket 是否传输信息
+                                state.token_alloc[token_index] = Token::Poll { fd };
+                                state.poll(&mut sq, fd, token_index);
+                            }
+                        }
+                    }
+
+                    Token::Expire => unreachable!(),
+
+                    Token::Write { fd, data, offset } => {
+                        // write(send) 事件返回，此时的结果是写字节数
+                        if !state.sent(fd, token_index) {
+                            continue;
+                        }
+                        let offset = offset + ret as usize;
+                        if offset < data.len() {
+                            // 如果没写完的话则从偏移量处继续写，写完之前不发送下一帧
+                            state.send(&mut sq, fd, data, offset);
+                        } else {
+                            state.write_done(&mut sq, fd);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 关闭连接的读写，轮询的读取事件随后会读到连接结束，照常关闭连接
+fn shutdown(fd: RawFd) {
+    unsafe {
+        libc::shutdown(fd, libc::SHUT_RDWR);
+    }
+}
@@ -0,0 +1,430 @@
+use mio::net::{TcpListener, TcpStream};
+use mio::{Events, Interest, Poll, Registry, Token, Waker};
+
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Read};
+use std::net::{Shutdown, SocketAddr};
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::chat::{Action, ChatServer};
+use crate::tp::{SharedQueueThreadPool, ThreadPool, ThreadPoolBuilder, TimerWheel};
+
+use super::outbound::{Flushed, OutboundConfig, Outbox, Push, EXPIRE_INTERVAL};
+use super::{lock, pool_error, Backend, SharedServer};
+
+// Setup some tokens to allow us to identify which event is for which socket.
+const SERVER: Token = Token(0);
+// Token of the waker, pool workers use it to wake up the poll loop.
+const WAKER: Token = Token(1);
+
+const BUFFER_SIZE: usize = 4096;
+// 打印线程池统计信息的间隔
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+/// mio 事件循环加线程池：事件循环接收连接、读取数据并写出每个连接自己的发送队列，
+/// 读到的数据交给线程池处理，处理结果经由通道返回，并通过 `mio::Waker` 唤醒事件循环。
+/// 所有动作都在持有服务器的锁时放入通道，事件循环按消息序号的顺序转发
+pub struct MioBackend {
+    addr: SocketAddr,
+    pool: ThreadPoolBuilder,
+    buffer_size: usize,
+    outbound: OutboundConfig,
+}
+
+impl MioBackend {
+    pub fn new(addr: SocketAddr, pool: ThreadPoolBuilder) -> Self {
+        Self {
+            addr,
+            pool,
+            buffer_size: BUFFER_SIZE,
+            outbound: OutboundConfig::default(),
+        }
+    }
+
+    /// 每次读取的缓冲区大小
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// 每个连接的发送队列的上限与慢消费者的处理方式
+    pub fn outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+}
+
+impl Backend for MioBackend {
+    fn run(self, server: ChatServer) -> io::Result<()> {
+        // Create a poll instance.
+        let mut poll = Poll::new()?;
+        // Create storage for events.
+        let mut events = Events::with_capacity(128);
+
+        // Setup the TCP server socket.
+        let mut listener = TcpListener::bind(self.addr)?;
+        println!("Server listen on {}", listener.local_addr()?);
+
+        // Register the server with poll we can receive events for it.
+        poll.registry()
+            .register(&mut listener, SERVER, Interest::READABLE)?;
+
+        let tp: SharedQueueThreadPool = self.pool.build().map_err(pool_error)?;
+        let tp = Arc::new(tp);
+        // 定时器由事件循环驱动，poll 最多等待到最早的定时器到期或下一次检查慢消费者
+        let timers = TimerWheel::new(Arc::clone(&tp), Duration::from_millis(100), 512);
+        {
+            let tp = Arc::clone(&tp);
+            timers.schedule_every(STATS_INTERVAL, move || println!("[Stats] {}", tp.stats()));
+        }
+
+        let (done, processed) = mpsc::channel();
+        let workers = Workers {
+            tp,
+            server: Arc::new(Mutex::new(server)),
+            done,
+            waker: Arc::new(Waker::new(poll.registry(), WAKER)?),
+        };
+        let mut connections = Connections {
+            registry: poll.registry().try_clone()?,
+            streams: HashMap::new(),
+            outbox: Outbox::new(self.outbound),
+            writing: HashSet::new(),
+            resumed: vec![],
+            buf: vec![0; self.buffer_size],
+        };
+        // Unique token for each incoming connection.
+        let mut unique_token = Token(WAKER.0 + 1);
+        let mut next_expire = Instant::now() + EXPIRE_INTERVAL;
+
+        loop {
+            let now = Instant::now();
+            let expire = next_expire.saturating_duration_since(now);
+            let timeout = timers.next_timeout(now).map_or(expire, |timeout| timeout.min(expire));
+            if let Err(err) = poll.poll(&mut events, Some(timeout)) {
+                if interrupted(&err) {
+                    continue;
+                }
+                return Err(err);
+            }
+            let now = Instant::now();
+            timers.advance(now);
+            // 断开让生产者暂停过久的慢消费者
+            if now >= next_expire {
+                next_expire = now + EXPIRE_INTERVAL;
+                for id in connections.outbox.expire(now) {
+                    connections.shutdown(Token(id));
+                }
+            }
+
+            for event in events.iter() {
+                match event.token() {
+                    SERVER => loop {
+                        // Received an event for the TCP server socket, which
+                        // indicates we can accept an connection.
+                        let (mut connection, address) = match listener.accept() {
+                            Ok((connection, address)) => (connection, address),
+                            // If we get a `WouldBlock` error we know our
+                            // listener has no more incoming connections queued,
+                            // so we can return to polling and wait for some
+                            // more.
+                            Err(ref err) if would_block(err) => break,
+                            Err(ref err) if interrupted(err) => continue,
+                            Err(err) => return Err(err),
+                        };
+
+                        let mut server = lock(&workers.server);
+                        if server.is_full() {
+                            println!("Reject {}: too many connections", address);
+                            continue;
+                        }
+
+                        let token = next(&mut unique_token);
+                        poll.registry()
+                            .register(&mut connection, token, Interest::READABLE)?;
+                        connections.streams.insert(
+                            token,
+                            Connection {
+                                stream: connection,
+                                processing: false,
+                            },
+                        );
+                        connections.outbox.open(token.0);
+
+                        let actions = server.connect(token.0, address);
+                        workers.queue(actions);
+                    },
+                    // The pool finished processing data read from connections,
+                    // the results are drained below.
+                    WAKER => (),
+                    token => {
+                        // Sporadic events happen, we can safely ignore them.
+                        if !connections.streams.contains_key(&token) {
+                            continue;
+                        }
+                        if event.is_writable() {
+                            connections.flush(token);
+                        }
+                        if event.is_readable() {
+                            connections.read(token, &workers);
+                        }
+                    }
+                }
+            }
+
+            loop {
+                // 唤醒可能被合并，取出所有结果
+                while let Ok((token, actions)) = processed.try_recv() {
+                    connections.processed(token, actions, &workers);
+                }
+                // 恢复读取的连接在暂停期间可能已经收到数据，边缘触发不会再通知，需要主动读取
+                match connections.resumed.pop() {
+                    Some(token) => connections.read(token, &workers),
+                    None => break,
+                }
+            }
+        }
+    }
+}
+
+/// 在线程池中处理读到的数据
+struct Workers {
+    tp: Arc<SharedQueueThreadPool>,
+    server: SharedServer,
+    /// 处理结果发回事件循环的通道，事件循环自己产生的动作没有 token
+    done: mpsc::Sender<(Option<Token>, Vec<Action>)>,
+    waker: Arc<Waker>,
+}
+
+impl Workers {
+    /// 把从连接读到的数据交给线程池处理，处理完成后唤醒事件循环
+    fn receive(&self, token: Token, data: Vec<u8>) {
+        let server = Arc::clone(&self.server);
+        let done = self.done.clone();
+        let waker = Arc::clone(&self.waker);
+        self.tp.spawn(move || {
+            let mut server = lock(&server);
+            let actions = server.receive(token.0, &data);
+            // 持有锁时放入通道，保证不同连接产生的消息按序号的顺序转发。
+            // 事件循环已经退出时发送失败，忽略即可
+            let sent = done.send((Some(token), actions)).is_ok();
+            drop(server);
+            if sent {
+                if let Err(err) = waker.wake() {
+                    eprintln!("[Error] Fail to wake event loop: {}", err);
+                }
+            }
+        });
+    }
+
+    /// 事件循环产生的动作同样经由通道转发，调用者需要持有服务器的锁
+    fn queue(&self, actions: Vec<Action>) {
+        let _ = self.done.send((None, actions));
+    }
+}
+
+struct Connection {
+    stream: TcpStream,
+    /// 线程池正在处理从这个连接读到的数据，处理完成前不再读取，保证数据按顺序交给服务器
+    processing: bool,
+}
+
+/// 所有连接与它们的发送队列，只在事件循环线程中使用
+struct Connections {
+    registry: Registry,
+    // Map of `Token` -> `Connection`.
+    streams: HashMap<Token, Connection>,
+    outbox: Outbox,
+    /// 关注了可写事件的连接
+    writing: HashSet<Token>,
+    /// 恢复读取后需要主动读取一次的连接
+    resumed: Vec<Token>,
+    // Buffer shared by all connections, the loop reads one at a time.
+    buf: Vec<u8>,
+}
+
+impl Connections {
+    /// 读取一次连接中的数据交给线程池，连接关闭时断开连接。
+    /// 连接正在被处理或者因为向慢消费者发送消息而被暂停时不读取，数据留在 socket 中
+    fn read(&mut self, token: Token, workers: &Workers) {
+        loop {
+            if self.outbox.is_paused(token.0) {
+                return;
+            }
+            let connection = match self.streams.get_mut(&token) {
+                Some(connection) if !connection.processing => connection,
+                _ => return,
+            };
+            match connection.stream.read(&mut self.buf) {
+                // Reading 0 bytes means the other side has closed the
+                // connection or is done writing, then so are we.
+                Ok(0) => return self.close(token, workers),
+                Ok(n) => {
+                    connection.processing = true;
+                    workers.receive(token, self.buf[..n].to_vec());
+                    return;
+                }
+                // Would block "errors" are the OS's way of saying that the
+                // connection is not actually ready to perform this I/O operation.
+                Err(ref err) if would_block(err) => return,
+                Err(ref err) if interrupted(err) => continue,
+                // Other errors we'll consider fatal for this connection.
+                Err(_) => return self.close(token, workers),
+            }
+        }
+    }
+
+    /// 线程池处理完连接的数据，执行产生的动作并继续读取。
+    /// 边缘触发在处理期间到达的数据不会再次通知，所以总是尝试读取一次。
+    /// token 为 `None` 时是事件循环自己产生的动作
+    fn processed(&mut self, token: Option<Token>, actions: Vec<Action>, workers: &Workers) {
+        let token = match token {
+            Some(token) => token,
+            None => return self.deliver(None, actions),
+        };
+        if let Some(connection) = self.streams.get_mut(&token) {
+            connection.processing = false;
+        }
+        self.deliver(Some(token), actions);
+        self.read(token, workers);
+    }
+
+    /// 关闭连接，连接只会在没有数据正在处理时关闭，此前读到的数据都已交给服务器
+    fn close(&mut self, token: Token, workers: &Workers) {
+        if let Some(mut connection) = self.streams.remove(&token) {
+            let _ = self.registry.deregister(&mut connection.stream);
+        }
+        self.writing.remove(&token);
+        let resumed = self.outbox.remove(token.0);
+        self.resumed.extend(resumed.into_iter().map(Token));
+        let mut server = lock(&workers.server);
+        let actions = server.disconnect(token.0);
+        workers.queue(actions);
+    }
+
+    /// 把数据放入发送队列并尽量写出，producer 为产生这些动作的连接
+    fn deliver(&mut self, producer: Option<Token>, actions: Vec<Action>) {
+        for action in actions {
+            match action {
+                Action::Send { to, data } => match self.outbox.push(to, data, producer.map(|token| token.0)) {
+                    Push::Queued | Push::Pause => self.flush(Token(to)),
+                    Push::Disconnect => self.shutdown(Token(to)),
+                },
+                // The connection becomes readable and is closed when the read returns 0.
+                Action::Close { id } => {
+                    if self.outbox.close(id) {
+                        self.shutdown(Token(id));
+                    }
+                }
+            }
+        }
+    }
+
+    /// 写出连接的发送队列，写不完时关注可写事件，写完后取消关注
+    fn flush(&mut self, token: Token) {
+        let connection = match self.streams.get_mut(&token) {
+            Some(connection) => &mut connection.stream,
+            None => return,
+        };
+        let mut shutdown = false;
+        let interest = match self.outbox.flush(token.0, connection) {
+            Ok(Flushed::Pending) if self.writing.insert(token) => Some(Interest::READABLE | Interest::WRITABLE),
+            Ok(Flushed::Drained) if self.writing.remove(&token) => Some(Interest::READABLE),
+            Ok(Flushed::Pending) | Ok(Flushed::Drained) => None,
+            Ok(Flushed::Close) | Err(_) => {
+                shutdown = true;
+                None
+            }
+        };
+        if let Some(interest) = interest {
+            if let Err(err) = self.registry.reregister(connection, token, interest) {
+                eprintln!("Fail to reregister connection {}: {}", token.0, err);
+                shutdown = true;
+            }
+        }
+        if shutdown {
+            self.shutdown(token);
+        }
+        let resumed = self.outbox.resume(token.0);
+        self.resumed.extend(resumed.into_iter().map(Token));
+    }
+
+    /// 关闭连接的读写，读到连接结束后照常关闭连接。
+    /// 连接被暂停读取时恢复读取，否则读不到连接结束
+    fn shutdown(&mut self, token: Token) {
+        if let Some(connection) = self.streams.get(&token) {
+            let _ = connection.stream.shutdown(Shutdown::Both);
+        }
+        if self.outbox.shutdown(token.0) {
+            self.resumed.push(token);
+        }
+    }
+}
+
+fn next(current: &mut Token) -> Token {
+    let next = current.0;
+    current.0 += 1;
+    Token(next)
+}
+
+fn would_block(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::WouldBlock
+}
+
+fn interrupted(err: &io::Error) -> bool {
+    err.kind() == io::ErrorKind::Interrupted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::{self, FrameDecoder};
+    use crate::protocol::{Envelope, Format, Kind};
+
+    const MESSAGES: usize = 1000;
+
+    fn frame(envelope: Envelope) -> Vec<u8> {
+        codec::encode(&envelope.encode(Format::Binary))
+    }
+
+    #[test]
+    fn fan_out_reaches_recipient_in_seq_order() {
+        let poll = Poll::new().unwrap();
+        let tp: SharedQueueThreadPool = ThreadPoolBuilder::new().num_threads(4).build().unwrap();
+        let (done, processed) = mpsc::channel();
+        let workers = Workers {
+            tp: Arc::new(tp),
+            server: Arc::new(Mutex::new(ChatServer::new())),
+            done,
+            waker: Arc::new(Waker::new(poll.registry(), WAKER).unwrap()),
+        };
+        for id in 1..=3 {
+            let mut server = lock(&workers.server);
+            server.connect(id, "127.0.0.1:1".parse().unwrap());
+            server.receive(id, &frame(Envelope::hello(format!("user{}", id))));
+        }
+
+        // 连接 1 与 2 同时发言，由线程池并发处理
+        for i in 0..MESSAGES {
+            for id in 1..=2 {
+                workers.receive(Token(id), frame(Envelope::chat(i.to_string())));
+            }
+        }
+        let mut seqs = vec![];
+        for _ in 0..MESSAGES * 2 {
+            let (_, actions) = processed.recv_timeout(Duration::from_secs(5)).unwrap();
+            for action in actions {
+                if let Action::Send { to: 3, data } = action {
+                    let mut decoder = FrameDecoder::new();
+                    decoder.extend(&data);
+                    let (envelope, _) = Envelope::decode(&decoder.next_frame().unwrap().unwrap()).unwrap();
+                    assert_eq!(envelope.kind, Kind::Chat);
+                    seqs.push(envelope.seq);
+                }
+            }
+        }
+        assert_eq!(seqs.len(), MESSAGES * 2);
+        assert!(seqs.windows(2).all(|pair| pair[0] < pair[1]), "{:?}", seqs);
+    }
+}
@@ -0,0 +1,556 @@
+//! 每个连接的发送队列：要发给连接的帧先放入队列，socket 可写时再写出。
+//! 队列的长度有上限，队列已满的连接被视为慢消费者，按照 `SlowConsumerPolicy` 处理
+
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::io::{self, ErrorKind, Write};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::chat::ConnId;
+
+/// 每个连接默认最多排队的帧数
+pub const DEFAULT_CAPACITY: usize = 1024;
+
+/// 生产者默认最多被暂停的时长
+pub const DEFAULT_PAUSE_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 后端调用 `Outbox::expire` 的间隔
+pub(crate) const EXPIRE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// 发送队列已满时的处理方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SlowConsumerPolicy {
+    /// 丢弃最早排队的帧
+    #[default]
+    DropOldest,
+    /// 断开慢消费者
+    Disconnect,
+    /// 暂停读取向慢消费者发送消息的连接，直到慢消费者的队列降到一半以下。
+    /// 没有生产者可以暂停时（例如其他连接断开的通知）队列仍会增长，达到两倍上限时断开慢消费者。
+    /// 生产者被暂停超过 `pause_timeout` 时同样断开慢消费者，暂停期间生产者断开也能及时发现
+    Pause,
+}
+
+impl FromStr for SlowConsumerPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(SlowConsumerPolicy::DropOldest),
+            "disconnect" => Ok(SlowConsumerPolicy::Disconnect),
+            "pause" => Ok(SlowConsumerPolicy::Pause),
+            _ => Err(format!("unknown slow consumer policy `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for SlowConsumerPolicy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            SlowConsumerPolicy::DropOldest => "drop-oldest",
+            SlowConsumerPolicy::Disconnect => "disconnect",
+            SlowConsumerPolicy::Pause => "pause",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 所有发送队列的统计信息，可以在服务器运行时从其他线程读取
+#[derive(Debug, Default)]
+pub struct OutboundStats {
+    queued: AtomicUsize,
+    max_depth: AtomicUsize,
+    dropped: AtomicU64,
+    disconnected: AtomicU64,
+    paused: AtomicU64,
+}
+
+impl OutboundStats {
+    /// 所有队列中正在排队的帧数
+    pub fn queued(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// 单个队列出现过的最大长度
+    pub fn max_depth(&self) -> usize {
+        self.max_depth.load(Ordering::Relaxed)
+    }
+
+    /// 因为 `DropOldest` 被丢弃的帧数
+    pub fn dropped(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    /// 被断开的慢消费者数量
+    pub fn disconnected(&self) -> u64 {
+        self.disconnected.load(Ordering::Relaxed)
+    }
+
+    /// 生产者被暂停读取的次数
+    pub fn paused(&self) -> u64 {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    fn enqueue(&self, depth: usize) {
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn dequeue(&self, frames: usize) {
+        self.queued.fetch_sub(frames, Ordering::Relaxed);
+    }
+}
+
+impl fmt::Display for OutboundStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "queued: {}, max depth: {}, dropped: {}, disconnected: {}, paused: {}",
+            self.queued(),
+            self.max_depth(),
+            self.dropped(),
+            self.disconnected(),
+            self.paused()
+        )
+    }
+}
+
+/// 发送队列的参数，克隆出的配置共享同一份统计信息
+#[derive(Debug, Clone)]
+pub struct OutboundConfig {
+    capacity: usize,
+    policy: SlowConsumerPolicy,
+    pause_timeout: Duration,
+    stats: Arc<OutboundStats>,
+}
+
+impl Default for OutboundConfig {
+    fn default() -> Self {
+        Self::new(DEFAULT_CAPACITY, SlowConsumerPolicy::default())
+    }
+}
+
+impl OutboundConfig {
+    /// capacity 为每个连接最多排队的帧数
+    pub fn new(capacity: usize, policy: SlowConsumerPolicy) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            policy,
+            pause_timeout: DEFAULT_PAUSE_TIMEOUT,
+            stats: Arc::new(OutboundStats::default()),
+        }
+    }
+
+    /// `Pause` 策略下生产者最多被暂停的时长，超过后断开让它等待的慢消费者
+    pub fn pause_timeout(mut self, timeout: Duration) -> Self {
+        self.pause_timeout = timeout;
+        self
+    }
+
+    pub fn stats(&self) -> Arc<OutboundStats> {
+        Arc::clone(&self.stats)
+    }
+}
+
+/// 放入队列的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Push {
+    Queued,
+    /// 连接是慢消费者，需要立即关闭，队列已被清空
+    Disconnect,
+    /// 生产者已被暂停，后端需要停止读取生产者，直到 `resume` 返回它
+    Pause,
+}
+
+/// 写出队列的结果
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Flushed {
+    /// socket 缓冲区已满，需要等待可写
+    Pending,
+    Drained,
+    /// 队列已写完且连接等待关闭
+    Close,
+}
+
+#[derive(Debug, Default)]
+struct Queue {
+    frames: VecDeque<Arc<[u8]>>,
+    /// 第一帧已经写出的字节数
+    written: usize,
+    /// 写完已排队的数据后关闭连接
+    closing: bool,
+    /// 作为慢消费者被断开，之后的帧直接丢弃
+    discarding: bool,
+    /// 因为向慢消费者发送消息而暂停读取
+    paused: bool,
+    /// 等待这个连接的队列变短的生产者
+    waiters: Vec<ConnId>,
+    /// 开始有生产者等待的时刻
+    blocking_since: Option<Instant>,
+}
+
+impl Queue {
+    /// 作为慢消费者断开，清空队列并丢弃之后的帧。
+    /// 等待它的生产者在连接关闭、`remove` 时恢复
+    fn disconnect(&mut self, stats: &OutboundStats) {
+        stats.dequeue(self.frames.len());
+        stats.disconnected.fetch_add(1, Ordering::Relaxed);
+        self.frames.clear();
+        self.written = 0;
+        self.discarding = true;
+        self.closing = true;
+    }
+}
+
+/// 一个后端中所有连接的发送队列
+#[derive(Debug)]
+pub(crate) struct Outbox {
+    config: OutboundConfig,
+    queues: HashMap<ConnId, Queue>,
+}
+
+impl Outbox {
+    pub(crate) fn new(config: OutboundConfig) -> Self {
+        Self {
+            config,
+            queues: HashMap::new(),
+        }
+    }
+
+    pub(crate) fn open(&mut self, id: ConnId) {
+        self.queues.insert(id, Queue::default());
+    }
+
+    /// 删除连接的队列，返回可以恢复读取的生产者
+    pub(crate) fn remove(&mut self, id: ConnId) -> Vec<ConnId> {
+        match self.queues.remove(&id) {
+            Some(queue) => {
+                self.config.stats.dequeue(queue.frames.len());
+                self.unpause(queue.waiters)
+            }
+            None => vec![],
+        }
+    }
+
+    /// 把帧放入 to 的队列，producer 为产生这条消息的连接，`Pause` 策略下可能被暂停
+    pub(crate) fn push(&mut self, to: ConnId, data: Arc<[u8]>, producer: Option<ConnId>) -> Push {
+        let capacity = self.config.capacity;
+        let stats = &self.config.stats;
+        let queue = match self.queues.get_mut(&to) {
+            Some(queue) if !queue.discarding => queue,
+            _ => return Push::Queued,
+        };
+
+        let full = queue.frames.len() >= capacity;
+        let overflow = match self.config.policy {
+            SlowConsumerPolicy::DropOldest if full => {
+                // 已经写出一部分的帧不能丢弃，否则会破坏分帧
+                let oldest = if queue.written > 0 { 1 } else { 0 };
+                if queue.frames.remove(oldest).is_some() {
+                    stats.dequeue(1);
+                    stats.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+                false
+            }
+            SlowConsumerPolicy::Disconnect => full,
+            SlowConsumerPolicy::Pause => queue.frames.len() >= capacity * 2,
+            SlowConsumerPolicy::DropOldest => false,
+        };
+        if overflow {
+            eprintln!("Disconnect slow consumer {}: {} frames queued", to, queue.frames.len());
+            queue.disconnect(stats);
+            return Push::Disconnect;
+        }
+
+        queue.frames.push_back(data);
+        stats.enqueue(queue.frames.len());
+        if self.config.policy != SlowConsumerPolicy::Pause || queue.frames.len() <= capacity {
+            return Push::Queued;
+        }
+        let producer = match producer {
+            Some(producer) => producer,
+            None => return Push::Queued,
+        };
+        match self.queues.get_mut(&producer) {
+            Some(state) if !state.paused => {
+                println!("Pause reading {}: {} is a slow consumer", producer, to);
+                state.paused = true;
+                stats.paused.fetch_add(1, Ordering::Relaxed);
+            }
+            _ => return Push::Queued,
+        }
+        if let Some(queue) = self.queues.get_mut(&to) {
+            queue.waiters.push(producer);
+            queue.blocking_since.get_or_insert_with(Instant::now);
+        }
+        Push::Pause
+    }
+
+    /// 发送完已排队的数据后关闭连接，队列已经为空时返回 `true`，调用者需要立即关闭
+    pub(crate) fn close(&mut self, id: ConnId) -> bool {
+        match self.queues.get_mut(&id) {
+            Some(queue) => {
+                queue.closing = true;
+                queue.frames.is_empty()
+            }
+            None => false,
+        }
+    }
+
+    /// 以非阻塞方式写出队列，直到队列为空或 socket 缓冲区已满
+    pub(crate) fn flush<W: Write>(&mut self, id: ConnId, writer: &mut W) -> io::Result<Flushed> {
+        let queue = match self.queues.get_mut(&id) {
+            Some(queue) => queue,
+            None => return Ok(Flushed::Drained),
+        };
+        while let Some(frame) = queue.frames.front() {
+            match writer.write(&frame[queue.written..]) {
+                Ok(0) => return Err(ErrorKind::WriteZero.into()),
+                Ok(n) => {
+                    queue.written += n;
+                    if queue.written == frame.len() {
+                        queue.frames.pop_front();
+                        queue.written = 0;
+                        self.config.stats.dequeue(1);
+                    }
+                }
+                Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+                Err(ref err) if err.kind() == ErrorKind::WouldBlock => return Ok(Flushed::Pending),
+                Err(err) => return Err(err),
+            }
+        }
+        Ok(if queue.closing { Flushed::Close } else { Flushed::Drained })
+    }
+
+    /// 取出下一帧交给异步的写操作，帧写完之前不要再取出同一连接的下一帧
+    pub(crate) fn pop(&mut self, id: ConnId) -> Option<Arc<[u8]>> {
+        let frame = self.queues.get_mut(&id)?.frames.pop_front()?;
+        self.config.stats.dequeue(1);
+        Some(frame)
+    }
+
+    /// 队列降到上限的一半以下时，返回等待它的生产者，后端需要恢复读取这些连接
+    pub(crate) fn resume(&mut self, id: ConnId) -> Vec<ConnId> {
+        let waiters = match self.queues.get_mut(&id) {
+            Some(queue) if queue.frames.len() <= self.config.capacity / 2 => {
+                queue.blocking_since = None;
+                std::mem::take(&mut queue.waiters)
+            }
+            _ => return vec![],
+        };
+        self.unpause(waiters)
+    }
+
+    /// 断开让生产者等待超过 `pause_timeout` 的慢消费者，返回需要立即关闭的连接，
+    /// 后端需要每隔 `EXPIRE_INTERVAL` 调用一次
+    pub(crate) fn expire(&mut self, now: Instant) -> Vec<ConnId> {
+        if self.config.policy != SlowConsumerPolicy::Pause {
+            return vec![];
+        }
+        let timeout = self.config.pause_timeout;
+        let stats = &self.config.stats;
+        self.queues
+            .iter_mut()
+            .filter(|(_, queue)| !queue.discarding)
+            .filter(|(_, queue)| queue.blocking_since.is_some_and(|since| now.saturating_duration_since(since) >= timeout))
+            .map(|(&id, queue)| {
+                eprintln!("Disconnect slow consumer {}: producers paused for {:?}", id, timeout);
+                queue.disconnect(stats);
+                id
+            })
+            .collect()
+    }
+
+    /// 后端关闭连接的读写时调用。连接自己作为生产者被暂停时恢复它，
+    /// 返回 `true` 时后端需要重新读取它，否则读不到连接结束，连接不会被断开
+    pub(crate) fn shutdown(&mut self, id: ConnId) -> bool {
+        !self.unpause(vec![id]).is_empty()
+    }
+
+    /// 连接的读取是否被暂停
+    pub(crate) fn is_paused(&self, id: ConnId) -> bool {
+        self.queues.get(&id).is_some_and(|queue| queue.paused)
+    }
+
+    /// 连接是否等待关闭
+    pub(crate) fn is_closing(&self, id: ConnId) -> bool {
+        self.queues.get(&id).is_some_and(|queue| queue.closing)
+    }
+
+    /// 连接是否作为慢消费者被断开，队列已被清空，不需要等待正在进行的写操作完成
+    pub(crate) fn is_disconnected(&self, id: ConnId) -> bool {
+        self.queues.get(&id).is_some_and(|queue| queue.discarding)
+    }
+
+    /// 恢复仍然处于暂停状态的生产者，已经断开或恢复的连接会被跳过
+    fn unpause(&mut self, waiters: Vec<ConnId>) -> Vec<ConnId> {
+        waiters
+            .into_iter()
+            .filter(|waiter| match self.queues.get_mut(waiter) {
+                Some(queue) if queue.paused => {
+                    queue.paused = false;
+                    true
+                }
+                _ => false,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(byte: u8) -> Arc<[u8]> {
+        Arc::from(vec![byte; 4])
+    }
+
+    fn outbox(capacity: usize, policy: SlowConsumerPolicy, ids: &[ConnId]) -> Outbox {
+        let mut outbox = Outbox::new(OutboundConfig::new(capacity, policy).pause_timeout(Duration::from_secs(5)));
+        for &id in ids {
+            outbox.open(id);
+        }
+        outbox
+    }
+
+    /// 每次最多写出 chunk 字节，写满 budget 字节后返回 `WouldBlock`
+    struct Partial {
+        written: Vec<u8>,
+        chunk: usize,
+        budget: usize,
+    }
+
+    impl Write for Partial {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            let n = buf.len().min(self.chunk).min(self.budget - self.written.len());
+            if n == 0 {
+                return Err(ErrorKind::WouldBlock.into());
+            }
+            self.written.extend_from_slice(&buf[..n]);
+            Ok(n)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn drop_oldest_keeps_partially_written_frame() {
+        let mut outbox = outbox(2, SlowConsumerPolicy::DropOldest, &[1]);
+        outbox.push(1, frame(1), None);
+        outbox.push(1, frame(2), None);
+        let mut writer = Partial { written: vec![], chunk: 3, budget: 3 };
+        assert_eq!(outbox.flush(1, &mut writer).unwrap(), Flushed::Pending);
+
+        assert_eq!(outbox.push(1, frame(3), None), Push::Queued);
+        writer.budget = usize::MAX;
+        assert_eq!(outbox.flush(1, &mut writer).unwrap(), Flushed::Drained);
+        assert_eq!(writer.written, [1, 1, 1, 1, 3, 3, 3, 3]);
+        assert_eq!(outbox.config.stats.dropped(), 1);
+        assert_eq!(outbox.config.stats.queued(), 0);
+    }
+
+    #[test]
+    fn disconnect_discards_later_frames() {
+        let mut outbox = outbox(1, SlowConsumerPolicy::Disconnect, &[1]);
+        assert_eq!(outbox.push(1, frame(1), None), Push::Queued);
+        assert_eq!(outbox.push(1, frame(2), None), Push::Disconnect);
+        assert!(outbox.is_disconnected(1));
+        assert_eq!(outbox.push(1, frame(3), None), Push::Queued);
+        assert_eq!(outbox.pop(1), None);
+        assert_eq!(outbox.config.stats.disconnected(), 1);
+        assert_eq!(outbox.config.stats.queued(), 0);
+    }
+
+    #[test]
+    fn paused_producer_resumes_when_queue_is_half_empty() {
+        let mut outbox = outbox(2, SlowConsumerPolicy::Pause, &[1, 2]);
+        assert_eq!(outbox.push(1, frame(1), Some(2)), Push::Queued);
+        assert_eq!(outbox.push(1, frame(2), Some(2)), Push::Queued);
+        assert_eq!(outbox.push(1, frame(3), Some(2)), Push::Pause);
+        assert!(outbox.is_paused(2));
+
+        outbox.pop(1);
+        assert_eq!(outbox.resume(1), Vec::<ConnId>::new());
+        outbox.pop(1);
+        assert_eq!(outbox.resume(1), vec![2]);
+        assert!(!outbox.is_paused(2));
+    }
+
+    #[test]
+    fn removed_consumer_resumes_producers() {
+        let mut outbox = outbox(1, SlowConsumerPolicy::Pause, &[1, 2]);
+        outbox.push(1, frame(1), Some(2));
+        assert_eq!(outbox.push(1, frame(2), Some(2)), Push::Pause);
+        assert_eq!(outbox.remove(1), vec![2]);
+        assert!(!outbox.is_paused(2));
+    }
+
+    #[test]
+    fn expire_disconnects_consumer_that_keeps_producers_paused() {
+        let mut outbox = outbox(1, SlowConsumerPolicy::Pause, &[1, 2]);
+        let start = Instant::now();
+        outbox.push(1, frame(1), Some(2));
+        assert_eq!(outbox.push(1, frame(2), Some(2)), Push::Pause);
+        // 慢消费者自己也在等待生产者
+        assert_eq!(outbox.push(2, frame(3), Some(1)), Push::Queued);
+        assert_eq!(outbox.push(2, frame(4), Some(1)), Push::Pause);
+
+        assert_eq!(outbox.expire(start + Duration::from_secs(1)), Vec::<ConnId>::new());
+        let mut expired = outbox.expire(start + Duration::from_secs(6));
+        expired.sort_unstable();
+        assert_eq!(expired, vec![1, 2]);
+        assert!(outbox.is_disconnected(1) && outbox.is_closing(1));
+        // 两个连接都被暂停，关闭时需要恢复读取
+        assert!(outbox.shutdown(1));
+        assert!(outbox.shutdown(2));
+        assert!(!outbox.is_paused(1) && !outbox.is_paused(2));
+        // 已经断开的连接不会再次过期
+        assert_eq!(outbox.expire(start + Duration::from_secs(12)), Vec::<ConnId>::new());
+    }
+
+    #[test]
+    fn expire_ignores_other_policies() {
+        let mut outbox = outbox(1, SlowConsumerPolicy::DropOldest, &[1, 2]);
+        outbox.push(1, frame(1), Some(2));
+        outbox.push(1, frame(2), Some(2));
+        assert_eq!(outbox.expire(Instant::now() + Duration::from_secs(60)), Vec::<ConnId>::new());
+    }
+
+    #[test]
+    fn paused_producer_disconnected_as_slow_consumer_is_resumed() {
+        let mut outbox = outbox(1, SlowConsumerPolicy::Pause, &[1, 2, 3]);
+        // 2 向慢消费者 1 发送消息而被暂停
+        outbox.push(1, frame(1), Some(2));
+        assert_eq!(outbox.push(1, frame(2), Some(2)), Push::Pause);
+        // 2 自己也成为慢消费者，队列达到两倍上限后被断开
+        outbox.push(2, frame(3), None);
+        outbox.push(2, frame(4), None);
+        assert_eq!(outbox.push(2, frame(5), Some(3)), Push::Disconnect);
+        assert!(outbox.is_paused(2));
+        assert!(outbox.shutdown(2));
+        assert!(!outbox.is_paused(2));
+        // 没有被暂停的连接不需要恢复
+        assert!(!outbox.shutdown(2));
+        assert!(!outbox.shutdown(3));
+        // 1 的队列变短时 2 已经恢复，不会再次返回
+        outbox.pop(1);
+        outbox.pop(1);
+        assert_eq!(outbox.resume(1), Vec::<ConnId>::new());
+    }
+
+    #[test]
+    fn flush_closes_after_queued_frames() {
+        let mut outbox = outbox(4, SlowConsumerPolicy::DropOldest, &[1]);
+        outbox.push(1, frame(1), None);
+        assert!(!outbox.close(1));
+        let mut writer = Partial { written: vec![], chunk: 1, budget: usize::MAX };
+        assert_eq!(outbox.flush(1, &mut writer).unwrap(), Flushed::Close);
+        assert_eq!(writer.written, [1, 1, 1, 1]);
+        assert!(outbox.close(1));
+    }
+}
@@ -0,0 +1,117 @@
+use std::collections::HashMap;
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::sync::{Arc, Mutex};
+
+use crate::chat::{Action, ChatServer, ConnId};
+use crate::tp::{SharedQueueThreadPool, ThreadPool, ThreadPoolBuilder};
+
+use super::{lock, pool_error, Backend, SharedServer};
+
+const BUFFER_SIZE: usize = 1024;
+
+/// 所有连接的写端
+type Writers = Arc<Mutex<HashMap<ConnId, TcpStream>>>;
+
+/// 阻塞 I/O 加线程池：主线程接收连接，每个连接由线程池中的一个线程阻塞读取，
+/// 同时服务的连接数受线程池最大线程数限制
+pub struct ThreadPoolBackend {
+    addr: SocketAddr,
+    pool: ThreadPoolBuilder,
+    buffer_size: usize,
+}
+
+impl ThreadPoolBackend {
+    pub fn new(addr: SocketAddr, pool: ThreadPoolBuilder) -> Self {
+        Self {
+            addr,
+            pool,
+            buffer_size: BUFFER_SIZE,
+        }
+    }
+
+    /// 每次读取的缓冲区大小
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+}
+
+impl Backend for ThreadPoolBackend {
+    fn run(self, server: ChatServer) -> io::Result<()> {
+        let listener = TcpListener::bind(self.addr)?;
+        println!("Server listen on {}", listener.local_addr()?);
+        let tp: SharedQueueThreadPool = self.pool.build().map_err(pool_error)?;
+        let server: SharedServer = Arc::new(Mutex::new(server));
+        let writers: Writers = Arc::new(Mutex::new(HashMap::new()));
+
+        for (id, socket) in listener.incoming().enumerate() {
+            let (socket, addr) = match socket.and_then(|s| s.peer_addr().map(|addr| (s, addr))) {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    eprintln!("Fail to accept connection: {}", err);
+                    continue;
+                }
+            };
+            let mut core = lock(&server);
+            if core.is_full() {
+                println!("Reject {}: too many connections", addr);
+                continue;
+            }
+            lock(&writers).insert(id, socket.try_clone()?);
+            let actions = core.connect(id, addr);
+            deliver(&writers, actions);
+            drop(core);
+
+            let server = Arc::clone(&server);
+            let writers = Arc::clone(&writers);
+            let buffer_size = self.buffer_size;
+            tp.spawn(move || serve(id, socket, buffer_size, server, writers));
+        }
+        Ok(())
+    }
+}
+
+/// 在线程池中阻塞读取一个连接，直到连接关闭
+fn serve(id: ConnId, mut socket: TcpStream, buffer_size: usize, server: SharedServer, writers: Writers) {
+    let mut buf = vec![0; buffer_size];
+    loop {
+        match socket.read(&mut buf) {
+            Ok(0) => break,
+            Ok(n) => {
+                let mut server = lock(&server);
+                let actions = server.receive(id, &buf[..n]);
+                deliver(&writers, actions);
+            }
+            Err(ref err) if err.kind() == ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+    let mut server = lock(&server);
+    lock(&writers).remove(&id);
+    let actions = server.disconnect(id);
+    deliver(&writers, actions);
+}
+
+/// 发送动作产生的消息。调用者需要持有服务器的锁，保证不同连接产生的消息按序号的顺序写出，
+/// 加锁的顺序总是先服务器后写端
+fn deliver(writers: &Writers, actions: Vec<Action>) {
+    let mut writers = lock(writers);
+    for action in actions {
+        match action {
+            Action::Send { to, data } => {
+                if let Some(writer) = writers.get_mut(&to) {
+                    if let Err(err) = writer.write_all(&data) {
+                        eprintln!("Fail to send {} bytes to {}: {}", data.len(), to, err);
+                    }
+                }
+            }
+            // 关闭后阻塞在 read 上的线程会读到连接结束，照常断开
+            Action::Close { id } => {
+                if let Some(writer) = writers.get(&id) {
+                    let _ = writer.shutdown(Shutdown::Both);
+                }
+            }
+        }
+    }
+}
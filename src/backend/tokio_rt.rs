@@ -0,0 +1,251 @@
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::Notify;
+
+use std::collections::HashMap;
+use std::io;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+use crate::affinity;
+use crate::chat::{Action, ChatServer, ConnId};
+
+use super::outbound::{OutboundConfig, Outbox, EXPIRE_INTERVAL};
+use super::{lock, Backend};
+
+const BUFFER_SIZE: usize = 1024;
+
+/// 在所有连接中共享的数据
+struct Shared {
+    server: ChatServer,
+    /// 每个连接的发送队列
+    outbox: Outbox,
+    /// 唤醒连接的任务：队列中有新数据、连接需要关闭或者恢复读取
+    peers: HashMap<ConnId, Arc<Notify>>,
+}
+
+/// 连接的任务接下来要做的事
+enum Next {
+    Write(Arc<[u8]>),
+    Close,
+    /// 队列为空，paused 为 `true` 时只等待唤醒，不读取连接
+    Wait { paused: bool },
+}
+
+impl Shared {
+    /// 把数据放入发送队列并唤醒连接的任务，producer 为正在读取的连接
+    fn deliver(&mut self, producer: Option<ConnId>, actions: Vec<Action>) {
+        for action in actions {
+            let id = match action {
+                Action::Send { to, data } => {
+                    // 被断开的慢消费者的任务被唤醒后放弃正在进行的写操作并退出，
+                    // 被暂停的生产者就是当前的任务，下次等待时不会读取连接
+                    self.outbox.push(to, data, producer);
+                    to
+                }
+                // 任务写完已排队的数据就会退出
+                Action::Close { id } => {
+                    self.outbox.close(id);
+                    id
+                }
+            };
+            self.wake(id);
+        }
+    }
+
+    /// 取出连接的下一帧，同时唤醒可以恢复读取的生产者
+    fn next(&mut self, id: ConnId) -> Next {
+        let data = self.outbox.pop(id);
+        for producer in self.outbox.resume(id) {
+            self.wake(producer);
+        }
+        match data {
+            Some(data) => Next::Write(data),
+            None if self.outbox.is_closing(id) => Next::Close,
+            None => Next::Wait {
+                paused: self.outbox.is_paused(id),
+            },
+        }
+    }
+
+    fn wake(&self, id: ConnId) {
+        if let Some(peer) = self.peers.get(&id) {
+            peer.notify_one();
+        }
+    }
+}
+
+/// tokio 多线程运行时：每个连接一个异步任务，发送的数据经由连接自己的发送队列写出
+pub struct TokioBackend {
+    addr: SocketAddr,
+    worker_threads: Option<usize>,
+    worker_cores: Option<Vec<usize>>,
+    buffer_size: usize,
+    outbound: OutboundConfig,
+}
+
+impl TokioBackend {
+    pub fn new(addr: SocketAddr) -> Self {
+        Self {
+            addr,
+            worker_threads: None,
+            worker_cores: None,
+            buffer_size: BUFFER_SIZE,
+            outbound: OutboundConfig::default(),
+        }
+    }
+
+    /// 每个连接的发送队列的上限与慢消费者的处理方式
+    pub fn outbound(mut self, outbound: OutboundConfig) -> Self {
+        self.outbound = outbound;
+        self
+    }
+
+    /// 运行时的工作线程数，默认为 CPU 核心数
+    pub fn worker_threads(mut self, threads: usize) -> Self {
+        self.worker_threads = Some(threads);
+        self
+    }
+
+    /// 每次读取的缓冲区大小
+    pub fn buffer_size(mut self, size: usize) -> Self {
+        self.buffer_size = size;
+        self
+    }
+
+    /// 将运行时的工作线程依次绑定到这些 CPU 核心上
+    pub fn worker_cores(mut self, cores: Option<Vec<usize>>) -> Self {
+        self.worker_cores = cores;
+        self
+    }
+}
+
+impl Backend for TokioBackend {
+    fn run(self, server: ChatServer) -> io::Result<()> {
+        let mut builder = tokio::runtime::Builder::new_multi_thread();
+        builder.enable_all();
+        if let Some(threads) = self.worker_threads {
+            builder.worker_threads(threads);
+        }
+        if let Some(cores) = self.worker_cores.filter(|cores| !cores.is_empty()) {
+            let next = AtomicUsize::new(0);
+            builder.on_thread_start(move || {
+                let core = cores[next.fetch_add(1, Ordering::Relaxed) % cores.len()];
+                if let Err(err) = affinity::pin_current_thread(core) {
+                    eprintln!("[Error] Fail to pin worker to core {}: {}", core, err);
+                }
+            });
+        }
+        let runtime = builder.build()?;
+        let shared = Arc::new(Mutex::new(Shared {
+            server,
+            outbox: Outbox::new(self.outbound),
+            peers: HashMap::new(),
+        }));
+        runtime.block_on(serve(self.addr, self.buffer_size, shared))
+    }
+}
+
+async fn serve(addr: SocketAddr, buffer_size: usize, shared: Arc<Mutex<Shared>>) -> io::Result<()> {
+    let listener = TcpListener::bind(addr).await?;
+    println!("Server listen on {}", listener.local_addr()?);
+    tokio::spawn(expire(Arc::clone(&shared)));
+    let mut next_id: ConnId = 0;
+    loop {
+        let (socket, addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(err) => {
+                eprintln!("Fail to accept connection: {}", err);
+                continue;
+            }
+        };
+        let id = next_id;
+        let notify = Arc::new(Notify::new());
+        {
+            let mut shared = lock(&shared);
+            if shared.server.is_full() {
+                println!("Reject {}: too many connections", addr);
+                continue;
+            }
+            shared.peers.insert(id, Arc::clone(&notify));
+            shared.outbox.open(id);
+            let actions = shared.server.connect(id, addr);
+            shared.deliver(None, actions);
+        }
+        next_id += 1;
+
+        let shared = Arc::clone(&shared);
+        tokio::spawn(async move {
+            if let Err(err) = handle(id, socket, buffer_size, &notify, &shared).await {
+                eprintln!("Connection {} error: {}", addr, err);
+            }
+
+            let mut shared = lock(&shared);
+            shared.peers.remove(&id);
+            for producer in shared.outbox.remove(id) {
+                shared.wake(producer);
+            }
+            let actions = shared.server.disconnect(id);
+            shared.deliver(None, actions);
+        });
+    }
+}
+
+/// 定期断开让生产者暂停过久的慢消费者，唤醒它们的任务后任务会关闭连接
+async fn expire(shared: Arc<Mutex<Shared>>) {
+    let mut interval = tokio::time::interval(EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        let mut shared = lock(&shared);
+        // 队列已被清空并等待关闭，任务被唤醒后直接关闭连接
+        for id in shared.outbox.expire(Instant::now()) {
+            shared.wake(id);
+        }
+    }
+}
+
+/// 写出发送队列中的数据，队列为空时同时等待唤醒和连接上的数据，直到连接关闭。
+/// 连接向慢消费者发送消息而被暂停时不读取连接，直到被唤醒。
+/// 返回后 socket 被关闭
+async fn handle(
+    id: ConnId,
+    mut socket: TcpStream,
+    buffer_size: usize,
+    notify: &Notify,
+    shared: &Mutex<Shared>,
+) -> io::Result<()> {
+    let mut buf = vec![0u8; buffer_size];
+    loop {
+        let next = lock(shared).next(id);
+        match next {
+            Next::Write(data) => {
+                let write = socket.write_all(&data);
+                tokio::pin!(write);
+                loop {
+                    tokio::select! {
+                        result = &mut write => break result?,
+                        // 对端不读取时写操作不会完成，作为慢消费者被断开后直接关闭连接
+                        _ = notify.notified() => if lock(shared).outbox.is_disconnected(id) {
+                            return Ok(());
+                        },
+                    }
+                }
+            }
+            Next::Close => return Ok(()),
+            Next::Wait { paused: true } => notify.notified().await,
+            Next::Wait { paused: false } => tokio::select! {
+                _ = notify.notified() => (),
+                result = socket.read(&mut buf) => match result? {
+                    0 => return Ok(()),
+                    n => {
+                        let mut shared = lock(shared);
+                        let actions = shared.server.receive(id, &buf[..n]);
+                        shared.deliver(Some(id), actions);
+                    }
+                },
+            },
+        }
+    }
+}
@@ -1,39 +1,60 @@
+use server::codec::{read_frame, write_frame};
+use server::protocol::{Envelope, Format, Replay};
+
+use std::io::stdin;
 use std::net::TcpStream;
-use std::io::{ Read, Write, stdin, ErrorKind };
-use std::sync::mpsc;
 
+/// 用法：client [addr] [--json] [--nick <name>] [--last <n> | --since <seq>]，默认使用 `$USER` 作为昵称，
+/// `--last` 与 `--since` 在进入大厅时回放历史消息
 fn main() {
-    let mut client = TcpStream::connect("127.0.0.1:8080").expect("Fail to connect");
-    client.set_nonblocking(true).unwrap();
-    let (sender, receiver) = mpsc::channel::<Vec<u8>>();
-    std::thread::spawn(move || {
-        loop {
-            let mut buffer = [0; 1024];
-            match client.read_exact(&mut buffer) {
-                Ok(_) => {
-                    let msg = String::from_utf8(buffer.to_vec()).unwrap();
-                    println!("Client receive message： {}", msg);
-                },
-                Err(ref err) if err.kind() == ErrorKind::WouldBlock => (),
-
-                Err(err) => {
-                    println!("Client close cnnection beacause {}", err);
-                    break;
-                }
+    let mut addr = "127.0.0.1:8080".to_string();
+    let mut format = Format::Binary;
+    let mut nick = std::env::var("USER").unwrap_or_else(|_| "guest".to_string());
+    let mut replay = None;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--json" => format = Format::Json,
+            "--nick" => nick = args.next().expect("missing value for --nick"),
+            "--last" | "--since" => {
+                let value = args.next().unwrap_or_else(|| panic!("missing value for {}", arg));
+                let spec = format!("{} {}", &arg[2..], value);
+                replay = Some(spec.parse::<Replay>().expect("invalid replay request"));
             }
+            _ => addr = arg,
+        }
+    }
 
-            if let Ok(data) = receiver.try_recv() {
-                client.write(&data).expect("Fail to transform data into server");
+    let mut client = TcpStream::connect(&addr).expect("Fail to connect");
+    let hello = match replay {
+        Some(replay) => Envelope::hello(nick).with_replay(replay),
+        None => Envelope::hello(nick),
+    };
+    write_frame(&mut client, &hello.encode(format)).expect("Fail to send hello");
+    let mut reader = client.try_clone().expect("Fail to clone socket");
+    std::thread::spawn(move || loop {
+        match read_frame(&mut reader) {
+            Ok(Some(frame)) => match Envelope::decode(&frame) {
+                Ok((envelope, _)) => println!("{}", envelope),
+                Err(err) => println!("Client receive invalid message: {}", err),
+            },
+            Ok(None) => {
+                println!("Server closed connection");
+                std::process::exit(0);
+            }
+            Err(err) => {
+                println!("Client close connection because {}", err);
+                std::process::exit(1);
             }
         }
     });
 
     loop {
         let mut buffer = String::new();
-        stdin().read_line(&mut buffer).unwrap();
-        let message = buffer.trim().to_string();
-        let mut buf = message.into_bytes();
-        buf.resize(1024, 0);
-        sender.send(buf).expect("Fail to send message");
+        if stdin().read_line(&mut buffer).expect("Fail to read stdin") == 0 {
+            break;
+        }
+        let message = Envelope::chat(buffer.trim());
+        write_frame(&mut client, &message.encode(format)).expect("Fail to send message");
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,87 @@
+use server::backend::{
+    Backend, EpollBackend, IoUringBackend, MioBackend, OutboundConfig, ThreadPoolBackend, TokioBackend,
+};
+use server::chat::ChatServer;
+use server::config::{BackendKind, Config};
+use server::history::MessageLog;
+use server::tp::{OverflowPolicy, ThreadPoolBuilder};
+
+use std::io;
+use std::thread;
+use std::time::Duration;
+
+// epoll 后端线程池中最多排队的任务数，超过后阻塞事件循环
+const MAX_QUEUED_TASKS: usize = 1024;
+// 打印发送队列统计信息的间隔
+const STATS_INTERVAL: Duration = Duration::from_secs(30);
+
+fn main() {
+    let config = Config::from_env();
+    config.affinity.pin_reactor();
+    if let Err(err) = run(config) {
+        eprintln!("[Error] {}", err);
+        std::process::exit(1);
+    }
+}
+
+fn run(config: Config) -> io::Result<()> {
+    println!("Start {} server", config.backend);
+    let mut server = ChatServer::new().max_connections(config.max_connections);
+    if let Some(dir) = &config.history_dir {
+        let mut log = MessageLog::open(dir)?;
+        if let Some(size) = config.history_segment_size {
+            log = log.segment_size(size);
+        }
+        println!("Message history in {}, next seq {}", dir.display(), log.next_seq());
+        server = server.history(log);
+    }
+    let addr = config.addr();
+    let pool = config
+        .affinity
+        .configure(ThreadPoolBuilder::new().num_threads(config.workers));
+    if config.backend == BackendKind::IoUring && config.affinity.worker_cores.is_some() {
+        eprintln!("{} server has no worker threads, ignore --worker-cores", config.backend);
+    }
+
+    let outbound = OutboundConfig::new(config.outbound_capacity, config.slow_consumer)
+        .pause_timeout(Duration::from_secs(config.pause_timeout));
+    if config.backend != BackendKind::ThreadPool {
+        let stats = outbound.stats();
+        thread::spawn(move || loop {
+            thread::sleep(STATS_INTERVAL);
+            println!("[Outbound] {}", stats);
+        });
+    }
+
+    // 未指定缓冲区大小时使用后端的默认值
+    macro_rules! buffer_size {
+        ($backend:expr) => {
+            match config.buffer_size {
+                Some(size) => $backend.buffer_size(size),
+                None => $backend,
+            }
+        };
+    }
+
+    match config.backend {
+        BackendKind::ThreadPool => buffer_size!(ThreadPoolBackend::new(addr, pool)).run(server),
+        BackendKind::Mio => buffer_size!(MioBackend::new(addr, pool).outbound(outbound)).run(server),
+        BackendKind::Epoll => {
+            let pool = pool
+                .queue_capacity(MAX_QUEUED_TASKS)
+                .overflow_policy(OverflowPolicy::Block);
+            buffer_size!(EpollBackend::new(addr, pool).outbound(outbound)).run(server)
+        }
+        BackendKind::Tokio => {
+            let backend = TokioBackend::new(addr)
+                .worker_threads(config.workers as usize)
+                .worker_cores(config.affinity.worker_cores.clone())
+                .outbound(outbound);
+            buffer_size!(backend).run(server)
+        }
+        BackendKind::IoUring => {
+            let backend = IoUringBackend::new(addr, config.ring_depth).outbound(outbound);
+            buffer_size!(backend).run(server)
+        }
+    }
+}
@@ -0,0 +1,125 @@
+//! 在回显负载下比较线程池的任务队列实现：每个客户端线程发送一条消息后，
+//! 由线程池中的任务从服务端 socket 读出并写回，客户端收到回显后再发送下一条
+
+use server::tp::{
+    QueueBackend, SharedQueueThreadPool, ThreadPool, ThreadPoolBuilder, WorkStealingThreadPool,
+};
+
+use std::env;
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+struct BenchArgs {
+    threads: u32,
+    clients: usize,
+    messages: usize,
+    size: usize,
+}
+
+impl BenchArgs {
+    fn parse() -> Self {
+        let mut parsed = Self {
+            threads: 16,
+            clients: 64,
+            messages: 2000,
+            size: 1024,
+        };
+        let mut args = env::args().skip(1);
+        while let Some(arg) = args.next() {
+            let value = args
+                .next()
+                .and_then(|v| v.parse::<usize>().ok())
+                .unwrap_or_else(|| panic!("{} expects a number", arg));
+            match arg.as_str() {
+                "--threads" => parsed.threads = value as u32,
+                "--clients" => parsed.clients = value,
+                "--messages" => parsed.messages = value,
+                "--size" => parsed.size = value,
+                _ => panic!("unknown argument `{}`", arg),
+            }
+        }
+        parsed
+    }
+}
+
+fn main() {
+    let args = BenchArgs::parse();
+    println!(
+        "echo workload: {} threads, {} clients x {} messages of {} bytes",
+        args.threads, args.clients, args.messages, args.size
+    );
+    for (name, backend) in [("mutex queue", QueueBackend::Mutex), ("lock-free queue", QueueBackend::LockFree)] {
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(args.threads)
+            .max_threads(args.threads)
+            .queue_backend(backend)
+            .build()
+            .expect("Fail to build thread pool");
+        run(name, Arc::new(pool), &args);
+    }
+    let pool: WorkStealingThreadPool = ThreadPoolBuilder::new()
+        .num_threads(args.threads)
+        .build()
+        .expect("Fail to build thread pool");
+    run("work stealing", Arc::new(pool), &args);
+}
+
+fn run<P>(name: &str, pool: Arc<P>, args: &BenchArgs)
+where P: ThreadPool + Send + Sync + 'static {
+    let start = Instant::now();
+    let clients: Vec<_> = (0..args.clients)
+        .map(|_| {
+            let pool = Arc::clone(&pool);
+            let (messages, size) = (args.messages, args.size);
+            thread::spawn(move || echo_client(pool, messages, size))
+        })
+        .collect();
+    let mut latencies: Vec<Duration> = clients
+        .into_iter()
+        .flat_map(|client| client.join().expect("Client thread panicked"))
+        .collect();
+    let elapsed = start.elapsed();
+
+    latencies.sort();
+    println!(
+        "{:<16} {:>10.0} msg/s  p50 {:>10?}  p99 {:>10?}  max {:>10?}",
+        name,
+        latencies.len() as f64 / elapsed.as_secs_f64(),
+        percentile(&latencies, 0.5),
+        percentile(&latencies, 0.99),
+        latencies.last().copied().unwrap_or_default()
+    );
+}
+
+/// 已排序的延迟中的分位数，没有数据（例如 `--messages 0`）时为 0
+fn percentile(latencies: &[Duration], q: f64) -> Duration {
+    match latencies.len() {
+        0 => Duration::ZERO,
+        len => latencies[((len - 1) as f64 * q) as usize],
+    }
+}
+
+/// 一个客户端连接，返回每条消息的往返时间
+fn echo_client<P: ThreadPool>(pool: Arc<P>, messages: usize, size: usize) -> Vec<Duration> {
+    let (mut client, server) = UnixStream::pair().expect("Fail to create socket pair");
+    let server = Arc::new(server);
+    let message = vec![b'x'; size];
+    let mut reply = vec![0; size];
+    let mut latencies = Vec::with_capacity(messages);
+    for _ in 0..messages {
+        let sent = Instant::now();
+        client.write_all(&message).expect("Fail to write message");
+        let server = Arc::clone(&server);
+        pool.spawn(move || {
+            let mut buf = vec![0; size];
+            (&*server).read_exact(&mut buf).expect("Fail to read message");
+            (&*server).write_all(&buf).expect("Fail to echo message");
+        });
+        client.read_exact(&mut reply).expect("Fail to read echo");
+        latencies.push(sent.elapsed());
+    }
+    latencies
+}
@@ -0,0 +1,713 @@
+//! 聊天服务器的核心逻辑，与具体的 I/O 方式无关。
+//! 各个后端负责接收连接、读写 socket，并把连接建立、收到数据、连接断开
+//! 三种事件交给 `ChatServer`，再执行它返回的 `Action`。
+//! 连接中传输的数据使用 `codec` 中的长度前缀格式分帧，每一帧是一个 `protocol::Envelope`。
+//! 连接建立后需要先完成握手注册昵称，之后才能收发聊天消息。
+//! 每个连接同一时间在一个房间中，聊天消息只发给同一房间的成员，
+//! 以 `/` 开头的消息作为命令交给 `CommandRegistry` 处理。
+//! 启用 `MessageLog` 后房间中的消息会被持久化，客户端可以在握手时或通过 `/history` 回放
+
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::sync::Arc;
+
+use crate::codec::{self, FrameDecoder};
+use crate::history::MessageLog;
+use crate::protocol::{self, Envelope, Format, Kind, Replay, PROTOCOL_VERSION};
+
+mod builtin;
+mod command;
+mod rooms;
+
+pub use command::{Command, CommandError, CommandRegistry, Context, Handler};
+pub use rooms::LOBBY;
+use rooms::Rooms;
+
+/// 昵称与房间名的最大长度
+pub const MAX_NAME_LEN: usize = 32;
+
+/// 一次回放的最大消息数量
+pub const MAX_REPLAY: usize = 1000;
+
+/// 检查昵称是否合法：1 到 32 个字母、数字、`_` 或 `-`
+pub fn validate_nickname(nick: &str) -> Result<(), String> {
+    validate_name("nickname", nick)
+}
+
+/// 检查房间名是否合法，规则与昵称相同
+pub fn validate_room_name(room: &str) -> Result<(), String> {
+    validate_name("room name", room)
+}
+
+fn validate_name(what: &str, name: &str) -> Result<(), String> {
+    if name.is_empty() {
+        return Err(format!("{} can not be empty", what));
+    }
+    if name.chars().count() > MAX_NAME_LEN {
+        return Err(format!("{} is longer than {} characters", what, MAX_NAME_LEN));
+    }
+    if let Some(c) = name.chars().find(|c| !c.is_alphanumeric() && *c != '_' && *c != '-') {
+        return Err(format!("{} can not contain `{}`", what, c));
+    }
+    Ok(())
+}
+
+/// 连接编号，由后端分配，在连接存活期间唯一
+pub type ConnId = usize;
+
+/// `ChatServer` 要求后端执行的动作
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// 向连接发送数据
+    Send { to: ConnId, data: Arc<[u8]> },
+    /// 关闭连接，之前的 `Send` 仍会尽量发送，连接关闭后后端照常调用 `disconnect`
+    Close { id: ConnId },
+}
+
+/// 连接信息
+struct Session {
+    addr: SocketAddr,
+    decoder: FrameDecoder,
+    /// 客户端最近一次使用的格式，发给它的消息使用相同的格式
+    format: Format,
+    /// 握手完成前为 `None`，此时连接不会收到广播
+    nick: Option<String>,
+    /// 所在的房间，握手完成后加入大厅
+    room: String,
+}
+
+/// 聊天服务器核心，收到的聊天消息转发给同一房间中除发送者以外的连接
+pub struct ChatServer {
+    sessions: HashMap<ConnId, Session>,
+    max_connections: Option<usize>,
+    /// 已注册的昵称
+    nicks: HashMap<String, ConnId>,
+    rooms: Rooms,
+    /// 执行命令时需要同时借用服务器，所以放在 `Arc` 中
+    commands: Arc<CommandRegistry>,
+    /// 下一条发出的消息的序号
+    next_seq: u64,
+    history: Option<MessageLog>,
+}
+
+impl Default for ChatServer {
+    fn default() -> Self {
+        Self {
+            sessions: HashMap::new(),
+            max_connections: None,
+            nicks: HashMap::new(),
+            rooms: Rooms::default(),
+            commands: Arc::new(CommandRegistry::with_builtins()),
+            next_seq: 0,
+            history: None,
+        }
+    }
+}
+
+impl ChatServer {
+    /// 包含内置命令的服务器
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 注册命令，同名的命令（包括内置命令）会被替换
+    pub fn register_command(&mut self, command: Command) {
+        Arc::make_mut(&mut self.commands).register(command);
+    }
+
+    /// 限制同时存在的连接数，`None` 时不限制
+    pub fn max_connections(mut self, max: Option<usize>) -> Self {
+        self.max_connections = max;
+        self
+    }
+
+    /// 持久化房间中的消息，序号从上次运行分配的序号之后继续编号
+    pub fn history(mut self, log: MessageLog) -> Self {
+        self.next_seq = self.next_seq.max(log.next_seq());
+        self.history = Some(log);
+        self
+    }
+
+    /// 连接数是否已经达到上限，后端在接收连接后检查，达到上限时直接关闭新连接
+    pub fn is_full(&self) -> bool {
+        self.max_connections.is_some_and(|max| self.sessions.len() >= max)
+    }
+
+    /// 当前连接数量
+    pub fn connections(&self) -> usize {
+        self.sessions.len()
+    }
+
+    /// 新连接建立
+    pub fn connect(&mut self, id: ConnId, addr: SocketAddr) -> Vec<Action> {
+        println!("{} joined connection", addr);
+        self.sessions.insert(
+            id,
+            Session {
+                addr,
+                decoder: FrameDecoder::new(),
+                format: Format::default(),
+                nick: None,
+                room: LOBBY.to_string(),
+            },
+        );
+        vec![]
+    }
+
+    /// 从连接中收到数据，数据可以在任意位置被切分，每收到一个完整的帧处理一次
+    pub fn receive(&mut self, id: ConnId, data: &[u8]) -> Vec<Action> {
+        let session = match self.sessions.get_mut(&id) {
+            Some(session) => session,
+            None => return vec![],
+        };
+        session.decoder.extend(data);
+        let addr = session.addr;
+
+        let mut frames = vec![];
+        let mut invalid = false;
+        loop {
+            match session.decoder.next_frame() {
+                Ok(Some(frame)) => frames.push(frame),
+                Ok(None) => break,
+                Err(err) => {
+                    // 之后的数据无法再分帧，只能关闭连接
+                    eprintln!("[Error] Invalid frame from {}: {}", addr, err);
+                    invalid = true;
+                    break;
+                }
+            }
+        }
+
+        let mut actions = vec![];
+        for frame in frames {
+            println!("Receive {} bytes from {}", frame.len(), addr);
+            actions.extend(self.handle_frame(id, &frame));
+        }
+        if invalid {
+            actions.push(Action::Close { id });
+        }
+        actions
+    }
+
+    /// 连接断开
+    pub fn disconnect(&mut self, id: ConnId) -> Vec<Action> {
+        let session = match self.sessions.remove(&id) {
+            Some(session) => session,
+            None => return vec![],
+        };
+        println!("{} has left", session.addr);
+        let nick = match session.nick {
+            Some(nick) => nick,
+            None => return vec![],
+        };
+        self.nicks.remove(&nick);
+        self.rooms.leave(&session.room, id);
+        let left = Envelope::new(Kind::Leave, format!("{} has left", nick))
+            .from_sender(id as u64, nick)
+            .in_room(&session.room);
+        let left = self.stamp(left);
+        self.broadcast(&session.room, None, &left)
+    }
+
+    /// 处理一条完整的消息
+    fn handle_frame(&mut self, id: ConnId, frame: &[u8]) -> Vec<Action> {
+        let (envelope, format) = match Envelope::decode(frame) {
+            Ok(decoded) => decoded,
+            Err(err) => return vec![self.error(id, err.to_string())],
+        };
+        let nick = match self.sessions.get_mut(&id) {
+            Some(session) => {
+                session.format = format;
+                session.nick.clone()
+            }
+            None => return vec![],
+        };
+
+        match (nick, envelope.kind) {
+            (None, Kind::Hello) => self.handshake(id, envelope),
+            (None, _) => self.reject(id, "handshake required before sending messages".to_string()),
+            (Some(_), Kind::Hello) => vec![self.error(id, "already registered".to_string())],
+            (Some(nick), Kind::Chat) if envelope.body.starts_with('/') => self.command(id, &nick, &envelope.body),
+            (Some(nick), Kind::Chat) => {
+                let room = self.room_of(id);
+                let chat = Envelope::chat(envelope.body)
+                    .from_sender(id as u64, nick)
+                    .in_room(&room);
+                let chat = self.stamp(chat);
+                self.broadcast(&room, Some(id), &chat)
+            }
+            (Some(_), kind) => vec![self.error(id, format!("clients can not send {:?} messages", kind))],
+        }
+    }
+
+    /// 检查协议版本与昵称，通过后注册昵称并通知其他连接，再回放请求的历史消息
+    fn handshake(&mut self, id: ConnId, hello: Envelope) -> Vec<Action> {
+        let (version, replay) = builtin::split_word(&hello.body);
+        if version.parse::<u32>() != Ok(PROTOCOL_VERSION) {
+            let reason = format!(
+                "unsupported protocol version `{}`, expected {}",
+                version, PROTOCOL_VERSION
+            );
+            return self.reject(id, reason);
+        }
+        let replay = match replay {
+            "" => None,
+            replay => match replay.parse::<Replay>() {
+                Ok(replay) => Some(replay),
+                Err(reason) => return self.reject(id, reason),
+            },
+        };
+        let nick = hello.nick.unwrap_or_default();
+        if let Err(reason) = validate_nickname(&nick) {
+            return self.reject(id, reason);
+        }
+        if self.nicks.contains_key(&nick) {
+            return self.reject(id, format!("nickname `{}` is already taken", nick));
+        }
+
+        if let Some(session) = self.sessions.get_mut(&id) {
+            println!("{} registered as {}", session.addr, nick);
+            session.nick = Some(nick.clone());
+        }
+        self.nicks.insert(nick.clone(), id);
+        let welcome = self.stamp(Envelope::new(Kind::System, format!("Welcome, {}", nick)));
+        let mut actions = vec![self.send_to(id, &welcome)];
+        actions.extend(self.enter_room(id, &nick, LOBBY));
+        if let Some(replay) = replay {
+            match self.replay(id, LOBBY, replay) {
+                Ok(replayed) => actions.extend(replayed),
+                Err(reason) => actions.push(self.error(id, reason)),
+            }
+        }
+        actions
+    }
+
+    /// 按原样向连接重新发送房间中的历史消息，之前先发送一条说明回放数量的系统消息
+    fn replay(&mut self, id: ConnId, room: &str, replay: Replay) -> Result<Vec<Action>, String> {
+        let log = self.history.as_ref().ok_or("message history is disabled")?;
+        let result = match replay {
+            Replay::Last(n) => log.last(room, n.min(MAX_REPLAY)),
+            Replay::Since(seq) => log.since(room, seq, MAX_REPLAY),
+        };
+        let envelopes = result.map_err(|err| {
+            eprintln!("[Error] Fail to read history of {}: {}", room, err);
+            "fail to read message history".to_string()
+        })?;
+
+        let mut notice = format!("Replaying {} messages of {}", envelopes.len(), room);
+        if let (Replay::Since(_), Some(last)) = (replay, envelopes.last()) {
+            if envelopes.len() == MAX_REPLAY {
+                notice = format!("{}, use /history since {} for more", notice, last.seq);
+            }
+        }
+        let notice = self.stamp(Envelope::new(Kind::System, notice).in_room(room));
+        let mut actions = vec![self.send_to(id, &notice)];
+        actions.extend(envelopes.iter().map(|envelope| self.send_to(id, envelope)));
+        Ok(actions)
+    }
+
+    /// 处理以 `/` 开头的命令，命令失败时回复错误原因
+    fn command(&mut self, id: ConnId, nick: &str, line: &str) -> Vec<Action> {
+        let (name, args) = builtin::split_word(&line[1..]);
+        let handler = match self.commands.get(name) {
+            Some(command) => Arc::clone(&command.handler),
+            None => return vec![self.error(id, CommandError::UnknownCommand(name.to_string()).to_string())],
+        };
+        let mut ctx = Context::new(self, id, nick.to_string());
+        let result = handler(&mut ctx, args);
+        let mut actions = ctx.into_actions();
+        if let Err(err) = result {
+            actions.push(self.error(id, err.to_string()));
+        }
+        actions
+    }
+
+    /// 离开当前房间并进入 room，通知两个房间中的其他成员
+    fn enter_room(&mut self, id: ConnId, nick: &str, room: &str) -> Vec<Action> {
+        let mut actions = vec![];
+        let old = match self.sessions.get_mut(&id) {
+            Some(session) => std::mem::replace(&mut session.room, room.to_string()),
+            None => return actions,
+        };
+        // 握手时还不在任何房间中
+        if self.rooms.members(&old).any(|member| member == id) {
+            self.rooms.leave(&old, id);
+            let left = Envelope::new(Kind::Leave, format!("{} has left", nick))
+                .from_sender(id as u64, nick)
+                .in_room(&old);
+            let left = self.stamp(left);
+            actions.extend(self.broadcast(&old, None, &left));
+        }
+
+        self.rooms.join(room, id);
+        let joined = Envelope::new(Kind::Join, format!("{} has joined", nick))
+            .from_sender(id as u64, nick)
+            .in_room(room);
+        let joined = self.stamp(joined);
+        actions.extend(self.broadcast(room, Some(id), &joined));
+        let entered = match self.rooms.topic(room) {
+            Some(topic) => format!("You are now in {}, topic: {}", room, topic),
+            None => format!("You are now in {}", room),
+        };
+        let entered = self.stamp(Envelope::new(Kind::System, entered).in_room(room));
+        actions.push(self.send_to(id, &entered));
+        actions
+    }
+
+    /// 按昵称查找已注册的用户
+    fn find_user(&self, nick: &str) -> Option<ConnId> {
+        self.nicks.get(nick).copied()
+    }
+
+    /// 修改用户的昵称，调用者需要保证新昵称合法且没有被使用
+    fn rename(&mut self, id: ConnId, nick: &str) {
+        if let Some(session) = self.sessions.get_mut(&id) {
+            if let Some(old) = session.nick.replace(nick.to_string()) {
+                self.nicks.remove(&old);
+            }
+            println!("{} renamed to {}", session.addr, nick);
+        }
+        self.nicks.insert(nick.to_string(), id);
+    }
+
+    /// 连接所在的房间
+    fn room_of(&self, id: ConnId) -> String {
+        self.sessions
+            .get(&id)
+            .map_or_else(|| LOBBY.to_string(), |session| session.room.clone())
+    }
+
+    /// 回复拒绝的原因并关闭连接
+    fn reject(&mut self, id: ConnId, reason: String) -> Vec<Action> {
+        vec![self.error(id, reason), Action::Close { id }]
+    }
+
+    /// 为服务器发出的消息分配序号与时间戳
+    fn stamp(&mut self, mut envelope: Envelope) -> Envelope {
+        if let Some(log) = &mut self.history {
+            if let Err(err) = log.reserve(self.next_seq) {
+                eprintln!("[Error] Fail to reserve sequence number: {}", err);
+            }
+        }
+        envelope.seq = self.next_seq;
+        envelope.timestamp = protocol::now_millis();
+        self.next_seq += 1;
+        envelope
+    }
+
+    /// 向连接回复一条错误消息
+    fn error(&mut self, id: ConnId, reason: String) -> Action {
+        let error = self.stamp(Envelope::new(Kind::Error, reason));
+        self.send_to(id, &error)
+    }
+
+    /// 使用连接自己的格式编码消息
+    fn send_to(&self, id: ConnId, envelope: &Envelope) -> Action {
+        let format = self.sessions.get(&id).map(|session| session.format).unwrap_or_default();
+        Action::Send {
+            to: id,
+            data: codec::encode(&envelope.encode(format)).into(),
+        }
+    }
+
+    /// 向房间中除 except 以外的成员发送消息，每种格式只编码一次。
+    /// 加入与离开以外的消息会写入消息记录
+    fn broadcast(&mut self, room: &str, except: Option<ConnId>, envelope: &Envelope) -> Vec<Action> {
+        if let Some(log) = &mut self.history {
+            if !matches!(envelope.kind, Kind::Join | Kind::Leave) {
+                if let Err(err) = log.append(envelope) {
+                    eprintln!("[Error] Fail to write history: {}", err);
+                }
+            }
+        }
+        let mut encoded: HashMap<Format, Arc<[u8]>> = HashMap::new();
+        self.rooms
+            .members(room)
+            .filter(|id| Some(*id) != except)
+            .filter_map(|id| self.sessions.get(&id).map(|session| (id, session)))
+            .map(|(id, session)| Action::Send {
+                to: id,
+                data: Arc::clone(
+                    encoded
+                        .entry(session.format)
+                        .or_insert_with(|| codec::encode(&envelope.encode(session.format)).into()),
+                ),
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::{HashMap, HashSet, VecDeque};
+    use std::sync::{Arc, Mutex};
+
+    use super::{Action, ChatServer, ConnId, LOBBY, MAX_NAME_LEN};
+    use crate::codec::{self, FrameDecoder};
+    use crate::history::MessageLog;
+    use crate::protocol::{Envelope, Format, Kind, PROTOCOL_VERSION};
+    use crate::tp::{ManualThreadPool, ThreadPool};
+
+    const SEEDS: u64 = 8;
+
+    /// 服务器与每个连接收到的消息
+    #[derive(Default)]
+    struct Shared {
+        server: Mutex<ChatServer>,
+        inbox: Mutex<HashMap<ConnId, Vec<Envelope>>>,
+        closed: Mutex<HashSet<ConnId>>,
+    }
+
+    impl Shared {
+        fn deliver(&self, actions: Vec<Action>) {
+            for action in actions {
+                match action {
+                    Action::Send { to, data } => {
+                        let mut decoder = FrameDecoder::new();
+                        decoder.extend(&data);
+                        let frame = decoder.next_frame().unwrap().unwrap();
+                        assert_eq!(decoder.buffered(), 0);
+                        let (envelope, _) = Envelope::decode(&frame).unwrap();
+                        self.inbox.lock().unwrap().entry(to).or_default().push(envelope);
+                    }
+                    Action::Close { id } => {
+                        self.closed.lock().unwrap().insert(id);
+                    }
+                }
+            }
+        }
+    }
+
+    /// 像 mio 后端一样在线程池中处理收到的数据：同一连接的消息按顺序处理，
+    /// 不同连接之间的处理顺序由 `ManualThreadPool` 的种子决定
+    struct Harness {
+        pool: Arc<ManualThreadPool>,
+        shared: Arc<Shared>,
+    }
+
+    impl Harness {
+        /// 连接 1..=n 依次以 nicks 完成握手，并清空握手产生的消息
+        fn registered(seed: u64, nicks: &[&str]) -> Self {
+            Self::with_server(seed, ChatServer::new(), nicks)
+        }
+
+        fn with_server(seed: u64, server: ChatServer, nicks: &[&str]) -> Self {
+            let harness = Self {
+                pool: Arc::new(ManualThreadPool::with_seed(seed)),
+                shared: Arc::new(Shared {
+                    server: Mutex::new(server),
+                    ..Shared::default()
+                }),
+            };
+            for (i, nick) in nicks.iter().enumerate() {
+                let id = i + 1;
+                harness.connect(id);
+                harness.send(id, vec![Envelope::hello(*nick)]);
+                harness.pool.run_all();
+            }
+            harness.shared.inbox.lock().unwrap().clear();
+            harness
+        }
+
+        fn connect(&self, id: ConnId) {
+            let actions = self.shared.server.lock().unwrap().connect(id, "127.0.0.1:1".parse().unwrap());
+            self.shared.deliver(actions);
+        }
+
+        /// 提交连接发出的消息，前一条处理完后才提交下一条
+        fn send(&self, id: ConnId, messages: Vec<Envelope>) {
+            let frames = messages
+                .iter()
+                .map(|message| codec::encode(&message.encode(Format::Binary)))
+                .collect();
+            process(Arc::clone(&self.pool), Arc::clone(&self.shared), id, frames);
+        }
+
+        fn chat(&self, id: ConnId, body: &str) {
+            self.send(id, vec![Envelope::chat(body)]);
+        }
+
+        /// 执行所有任务并取出 id 收到的消息
+        fn take(&self, id: ConnId) -> Vec<Envelope> {
+            self.pool.run_all();
+            self.shared.inbox.lock().unwrap().remove(&id).unwrap_or_default()
+        }
+    }
+
+    fn process(pool: Arc<ManualThreadPool>, shared: Arc<Shared>, id: ConnId, mut frames: VecDeque<Vec<u8>>) {
+        let frame = match frames.pop_front() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let next = Arc::clone(&pool);
+        pool.spawn(move || {
+            let actions = shared.server.lock().unwrap().receive(id, &frame);
+            shared.deliver(actions);
+            process(next, shared, id, frames);
+        });
+    }
+
+    fn of_kind(envelopes: &[Envelope], kind: Kind) -> Vec<&Envelope> {
+        envelopes.iter().filter(|envelope| envelope.kind == kind).collect()
+    }
+
+    #[test]
+    fn broadcast_reaches_other_members_in_order() {
+        let nicks = ["alice", "bob", "carol"];
+        for seed in 0..SEEDS {
+            let harness = Harness::registered(seed, &nicks);
+            for id in 1..=nicks.len() {
+                let messages = (0..5).map(|i| Envelope::chat(format!("{}-{}", id, i))).collect();
+                harness.send(id, messages);
+            }
+            harness.pool.run_all();
+
+            for id in 1..=nicks.len() {
+                let inbox = harness.take(id);
+                let chats = of_kind(&inbox, Kind::Chat);
+                assert_eq!(chats.len(), 10, "seed {}", seed);
+                assert!(inbox.windows(2).all(|pair| pair[0].seq < pair[1].seq));
+                for sender in (1..=nicks.len()).filter(|sender| *sender != id) {
+                    let bodies: Vec<&str> = chats
+                        .iter()
+                        .filter(|chat| chat.sender == Some(sender as u64))
+                        .map(|chat| chat.body.as_str())
+                        .collect();
+                    let expected: Vec<String> = (0..5).map(|i| format!("{}-{}", sender, i)).collect();
+                    assert_eq!(bodies, expected, "seed {}", seed);
+                    assert!(chats
+                        .iter()
+                        .filter(|chat| chat.sender == Some(sender as u64))
+                        .all(|chat| chat.nick.as_deref() == Some(nicks[sender - 1]) && chat.room.as_deref() == Some(LOBBY)));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rooms_isolate_chat() {
+        for seed in 0..SEEDS {
+            let harness = Harness::registered(seed, &["alice", "bob", "carol"]);
+            harness.send(1, vec![Envelope::chat("/join rust"), Envelope::chat("in rust")]);
+            harness.send(2, vec![Envelope::chat("/join rust")]);
+            harness.chat(3, "in lobby");
+
+            // alice 加入 rust 之后才发言，carol 一定收不到
+            let carol = harness.take(3);
+            assert!(of_kind(&carol, Kind::Chat).is_empty(), "seed {}", seed);
+            assert_eq!(of_kind(&carol, Kind::Leave).len(), 2);
+            // bob 可能在 alice 发言之后才加入，alice 与 bob 也可能在 carol 发言之后才离开大厅
+            harness.take(1);
+            harness.take(2);
+
+            harness.chat(1, "again");
+            harness.chat(3, "lobby again");
+            let bob = harness.take(2);
+            let chats = of_kind(&bob, Kind::Chat);
+            assert_eq!(chats.len(), 1, "seed {}", seed);
+            assert_eq!(chats[0].body, "again");
+            assert_eq!(chats[0].room.as_deref(), Some("rust"));
+            assert!(of_kind(&harness.take(1), Kind::Chat).is_empty());
+            assert!(of_kind(&harness.take(3), Kind::Chat).is_empty());
+        }
+    }
+
+    #[test]
+    fn direct_message_reaches_only_recipient() {
+        for seed in 0..SEEDS {
+            let harness = Harness::registered(seed, &["alice", "bob", "carol"]);
+            harness.send(2, vec![Envelope::chat("/join rust")]);
+            harness.chat(1, "/msg bob secret");
+            harness.chat(3, "/msg nobody hello");
+            harness.pool.run_all();
+
+            let bob = harness.take(2);
+            let direct = of_kind(&bob, Kind::Direct);
+            assert_eq!(direct.len(), 1, "seed {}", seed);
+            assert_eq!(direct[0].body, "secret");
+            assert_eq!(direct[0].nick.as_deref(), Some("alice"));
+
+            let alice = harness.take(1);
+            assert!(of_kind(&alice, Kind::Direct).is_empty());
+            assert!(alice.iter().any(|envelope| envelope.body == "Message sent to bob"));
+
+            let carol = harness.take(3);
+            assert!(of_kind(&carol, Kind::Direct).is_empty());
+            assert_eq!(of_kind(&carol, Kind::Error).len(), 1);
+        }
+    }
+
+    #[test]
+    fn handshake_rejects_bad_hello_without_joining() {
+        let mut future_version = Envelope::hello("bob");
+        future_version.body = (PROTOCOL_VERSION + 1).to_string();
+        let cases = [
+            (future_version, "unsupported protocol version"),
+            (Envelope::hello(""), "can not be empty"),
+            (Envelope::hello("b".repeat(MAX_NAME_LEN + 1)), "longer than"),
+            (Envelope::hello("bob smith"), "can not contain ` `"),
+            (Envelope::hello("alice"), "already taken"),
+        ];
+        for (hello, reason) in cases {
+            let harness = Harness::registered(0, &["alice"]);
+            harness.connect(2);
+            harness.send(2, vec![hello]);
+
+            let bob = harness.take(2);
+            assert_eq!(bob.len(), 1, "{}", reason);
+            assert_eq!(bob[0].kind, Kind::Error);
+            assert!(bob[0].body.contains(reason), "{}", bob[0].body);
+            assert!(harness.shared.closed.lock().unwrap().contains(&2));
+            // 被拒绝的连接不会进入大厅
+            assert_eq!(harness.take(1), vec![], "{}", reason);
+            let server = harness.shared.server.lock().unwrap();
+            assert_eq!(server.nicks.len(), 1);
+            assert_eq!(server.rooms.members(LOBBY).count(), 1);
+        }
+    }
+
+    #[test]
+    fn quit_closes_connection() {
+        let harness = Harness::registered(0, &["alice", "bob"]);
+        harness.chat(1, "/quit");
+        let alice = harness.take(1);
+        assert_eq!(alice.last().map(|envelope| envelope.body.as_str()), Some("Bye"));
+        assert!(harness.shared.closed.lock().unwrap().contains(&1));
+
+        let actions = harness.shared.server.lock().unwrap().disconnect(1);
+        harness.shared.deliver(actions);
+        let bob = harness.take(2);
+        assert_eq!(of_kind(&bob, Kind::Leave).len(), 1);
+        assert_eq!(harness.shared.server.lock().unwrap().connections(), 1);
+    }
+
+    #[test]
+    fn sequence_numbers_continue_after_restart() {
+        let dir = std::env::temp_dir().join(format!("labs-net-chat-restart-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let server = ChatServer::new().history(MessageLog::open(&dir).unwrap());
+        let harness = Harness::with_server(0, server, &["alice", "bob"]);
+        harness.chat(1, "before");
+        // 私信与回复不会被记录，但同样占用序号，数量多于重启后握手占用的序号
+        harness.send(1, (0..10).map(|_| Envelope::chat("/msg bob psst")).collect());
+        let issued: Vec<u64> = [harness.take(1), harness.take(2)].iter().flatten().map(|envelope| envelope.seq).collect();
+        let last_seen = *issued.iter().max().unwrap();
+        drop(harness);
+
+        let server = ChatServer::new().history(MessageLog::open(&dir).unwrap());
+        let harness = Harness::with_server(0, server, &["alice", "bob"]);
+        harness.chat(1, "after");
+        let bob = harness.take(2);
+        let after = of_kind(&bob, Kind::Chat)[0].seq;
+        assert!(after > last_seen, "{} reused after {}", after, last_seen);
+
+        harness.chat(2, &format!("/history since {}", last_seen));
+        let replayed = harness.take(2);
+        assert_eq!(replayed.len(), 2);
+        assert_eq!(replayed[0].body, format!("Replaying 1 messages of {}", LOBBY));
+        assert_eq!(replayed[1].body, "after");
+        assert_eq!(replayed[1].seq, after);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,155 @@
+//! 内置的聊天命令
+
+use super::command::{Command, CommandError, Context};
+use super::LOBBY;
+use crate::protocol::{Kind, Replay};
+
+/// `/history` 不带参数时回放的消息数量
+const DEFAULT_HISTORY: usize = 20;
+
+pub(super) fn commands() -> Vec<Command> {
+    vec![
+        Command::new("help", "/help", "list available commands", help),
+        Command::new("join", "/join <room>", "leave the current room and join (or create) a room", join),
+        Command::new("leave", "/leave", "go back to the lobby", leave),
+        Command::new("rooms", "/rooms", "list rooms and their member counts", rooms),
+        Command::new("who", "/who [room]", "list the members of a room", who),
+        Command::new("msg", "/msg <nick> <text>", "send a direct message to one user", msg),
+        Command::new("nick", "/nick <nick>", "change your nickname", nick),
+        Command::new("me", "/me <action>", "describe what you are doing", me),
+        Command::new("topic", "/topic [text]", "show or set the topic of the current room", topic),
+        Command::new(
+            "history",
+            "/history [n | since <seq>]",
+            "replay the last n messages of the current room, or those after a sequence number",
+            history,
+        ),
+        Command::new("quit", "/quit", "disconnect from the server", quit),
+    ]
+}
+
+/// 拆分出第一个单词与剩余的部分，两者都去掉了首尾空白
+pub(super) fn split_word(s: &str) -> (&str, &str) {
+    let s = s.trim();
+    match s.split_once(char::is_whitespace) {
+        Some((word, rest)) => (word, rest.trim()),
+        None => (s, ""),
+    }
+}
+
+/// 只接受一个单词作为参数
+fn single_word<'a>(args: &'a str, usage: &'static str) -> Result<&'a str, CommandError> {
+    match split_word(args) {
+        (word, "") if !word.is_empty() => Ok(word),
+        _ => Err(CommandError::Usage(usage)),
+    }
+}
+
+fn help(ctx: &mut Context<'_>, _args: &str) -> Result<(), CommandError> {
+    let commands = ctx.commands();
+    let lines: Vec<String> = commands
+        .iter()
+        .map(|command| format!("{} - {}", command.usage(), command.summary()))
+        .collect();
+    ctx.reply(format!("Commands:\n{}", lines.join("\n")));
+    Ok(())
+}
+
+fn join(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let room = single_word(args, "/join <room>")?;
+    ctx.enter_room(room)
+}
+
+fn leave(ctx: &mut Context<'_>, _args: &str) -> Result<(), CommandError> {
+    if ctx.room() == LOBBY {
+        return Err(CommandError::Failed(format!("can not leave the {}", LOBBY)));
+    }
+    ctx.enter_room(LOBBY)
+}
+
+fn rooms(ctx: &mut Context<'_>, _args: &str) -> Result<(), CommandError> {
+    let list: Vec<String> = ctx
+        .rooms()
+        .into_iter()
+        .map(|(room, members)| format!("{} ({})", room, members))
+        .collect();
+    ctx.reply(format!("Rooms: {}", list.join(", ")));
+    Ok(())
+}
+
+fn who(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let room = match args {
+        "" => ctx.room(),
+        room => single_word(room, "/who [room]")?.to_string(),
+    };
+    let members = ctx.members(&room);
+    if members.is_empty() {
+        return Err(CommandError::Failed(format!("no such room `{}`", room)));
+    }
+    ctx.reply(format!("Members of {}: {}", room, members.join(", ")));
+    Ok(())
+}
+
+fn msg(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let (to, text) = split_word(args);
+    if to.is_empty() || text.is_empty() {
+        return Err(CommandError::Usage("/msg <nick> <text>"));
+    }
+    ctx.direct(to, text)?;
+    ctx.reply(format!("Message sent to {}", to));
+    Ok(())
+}
+
+fn nick(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let new = single_word(args, "/nick <nick>")?;
+    if new == ctx.nick() {
+        return Err(CommandError::Failed(format!("you are already {}", new)));
+    }
+    let old = ctx.nick().to_string();
+    ctx.rename(new)?;
+    ctx.broadcast(Kind::System, format!("{} is now known as {}", old, new), true);
+    Ok(())
+}
+
+fn me(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    if args.is_empty() {
+        return Err(CommandError::Usage("/me <action>"));
+    }
+    let action = format!("{} {}", ctx.nick(), args);
+    ctx.broadcast(Kind::System, action, true);
+    Ok(())
+}
+
+fn topic(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let room = ctx.room();
+    if args.is_empty() {
+        match ctx.topic(&room) {
+            Some(topic) => ctx.reply(format!("Topic of {}: {}", room, topic)),
+            None => ctx.reply(format!("{} has no topic", room)),
+        }
+        return Ok(());
+    }
+    ctx.set_topic(&room, Some(args.to_string()));
+    let notice = format!("{} set the topic to: {}", ctx.nick(), args);
+    ctx.broadcast(Kind::System, notice, true);
+    Ok(())
+}
+
+fn history(ctx: &mut Context<'_>, args: &str) -> Result<(), CommandError> {
+    let replay = match args {
+        "" => Replay::Last(DEFAULT_HISTORY),
+        args => match args.parse::<usize>() {
+            Ok(n) => Replay::Last(n),
+            Err(_) => args
+                .parse()
+                .map_err(|_| CommandError::Usage("/history [n | since <seq>]"))?,
+        },
+    };
+    ctx.replay(replay)
+}
+
+fn quit(ctx: &mut Context<'_>, _args: &str) -> Result<(), CommandError> {
+    ctx.reply("Bye");
+    ctx.close();
+    Ok(())
+}
@@ -0,0 +1,257 @@
+//! 以 `/` 开头的聊天消息会被当作命令，按名称在 `CommandRegistry` 中查找处理函数。
+//! 处理函数通过 `Context` 访问会话与房间的状态，产生的消息由 `ChatServer` 交给后端发送
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::{validate_nickname, validate_room_name, Action, ChatServer, ConnId};
+use crate::protocol::{Envelope, Kind, Replay};
+
+/// 命令执行失败的原因，会作为 `Error` 消息回复给发送者
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CommandError {
+    /// 命令不存在
+    UnknownCommand(String),
+    /// 参数不正确，附带命令的用法
+    Usage(&'static str),
+    /// 用户不存在或已经离线
+    UnknownUser(String),
+    /// 昵称或房间名不合法
+    InvalidName(String),
+    /// 昵称已经被其他用户使用
+    NickTaken(String),
+    /// 其他原因
+    Failed(String),
+}
+
+impl fmt::Display for CommandError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CommandError::UnknownCommand(name) => write!(f, "unknown command `/{}`, try /help", name),
+            CommandError::Usage(usage) => write!(f, "usage: {}", usage),
+            CommandError::UnknownUser(nick) => write!(f, "no such user `{}`", nick),
+            CommandError::InvalidName(reason) => f.write_str(reason),
+            CommandError::NickTaken(nick) => write!(f, "nickname `{}` is already taken", nick),
+            CommandError::Failed(reason) => f.write_str(reason),
+        }
+    }
+}
+
+impl Error for CommandError {}
+
+/// 命令的处理函数，参数为命令名之后去掉首尾空白的部分
+pub type Handler = Arc<dyn Fn(&mut Context<'_>, &str) -> Result<(), CommandError> + Send + Sync>;
+
+/// 一条命令
+#[derive(Clone)]
+pub struct Command {
+    name: &'static str,
+    usage: &'static str,
+    summary: &'static str,
+    pub(super) handler: Handler,
+}
+
+impl Command {
+    /// usage 为完整的用法，例如 `/join <room>`，summary 为 `/help` 中显示的说明
+    pub fn new<F>(name: &'static str, usage: &'static str, summary: &'static str, handler: F) -> Self
+    where
+        F: Fn(&mut Context<'_>, &str) -> Result<(), CommandError> + Send + Sync + 'static,
+    {
+        Self {
+            name,
+            usage,
+            summary,
+            handler: Arc::new(handler),
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    pub fn usage(&self) -> &'static str {
+        self.usage
+    }
+
+    pub fn summary(&self) -> &'static str {
+        self.summary
+    }
+}
+
+impl fmt::Debug for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Command")
+            .field("name", &self.name)
+            .field("usage", &self.usage)
+            .finish()
+    }
+}
+
+/// 按名称保存的命令
+#[derive(Debug, Clone, Default)]
+pub struct CommandRegistry {
+    commands: BTreeMap<&'static str, Command>,
+}
+
+impl CommandRegistry {
+    /// 空的命令表
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 包含所有内置命令的命令表
+    pub fn with_builtins() -> Self {
+        let mut registry = Self::new();
+        for command in super::builtin::commands() {
+            registry.register(command);
+        }
+        registry
+    }
+
+    /// 注册命令，同名的命令会被替换
+    pub fn register(&mut self, command: Command) {
+        self.commands.insert(command.name, command);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Command> {
+        self.commands.get(name)
+    }
+
+    /// 按名称排序的所有命令
+    pub fn iter(&self) -> impl Iterator<Item = &Command> {
+        self.commands.values()
+    }
+}
+
+/// 执行命令时的上下文，代表发出命令的连接
+pub struct Context<'a> {
+    server: &'a mut ChatServer,
+    id: ConnId,
+    nick: String,
+    actions: Vec<Action>,
+}
+
+impl<'a> Context<'a> {
+    pub(super) fn new(server: &'a mut ChatServer, id: ConnId, nick: String) -> Self {
+        Self {
+            server,
+            id,
+            nick,
+            actions: vec![],
+        }
+    }
+
+    pub(super) fn into_actions(self) -> Vec<Action> {
+        self.actions
+    }
+
+    /// 发出命令的连接
+    pub fn id(&self) -> ConnId {
+        self.id
+    }
+
+    /// 发出命令的用户的昵称
+    pub fn nick(&self) -> &str {
+        &self.nick
+    }
+
+    /// 发出命令的用户所在的房间
+    pub fn room(&self) -> String {
+        self.server.room_of(self.id)
+    }
+
+    /// 服务器上已注册的命令
+    pub fn commands(&self) -> Arc<CommandRegistry> {
+        Arc::clone(&self.server.commands)
+    }
+
+    /// 向发出命令的用户回复一条系统消息
+    pub fn reply(&mut self, body: impl Into<String>) {
+        let envelope = self.server.stamp(Envelope::new(Kind::System, body));
+        self.actions.push(self.server.send_to(self.id, &envelope));
+    }
+
+    /// 以发出命令的用户的名义向当前房间发送消息，include_self 为 `false` 时不发给自己
+    pub fn broadcast(&mut self, kind: Kind, body: impl Into<String>, include_self: bool) {
+        let room = self.room();
+        let envelope = Envelope::new(kind, body)
+            .from_sender(self.id as u64, self.nick.as_str())
+            .in_room(&room);
+        let envelope = self.server.stamp(envelope);
+        let except = if include_self { None } else { Some(self.id) };
+        let actions = self.server.broadcast(&room, except, &envelope);
+        self.actions.extend(actions);
+    }
+
+    /// 向指定的用户发送私信
+    pub fn direct(&mut self, to: &str, body: impl Into<String>) -> Result<(), CommandError> {
+        let recipient = self.server.find_user(to).ok_or_else(|| CommandError::UnknownUser(to.to_string()))?;
+        let envelope = Envelope::new(Kind::Direct, body).from_sender(self.id as u64, self.nick.as_str());
+        let envelope = self.server.stamp(envelope);
+        self.actions.push(self.server.send_to(recipient, &envelope));
+        Ok(())
+    }
+
+    /// 离开当前房间并进入 room
+    pub fn enter_room(&mut self, room: &str) -> Result<(), CommandError> {
+        validate_room_name(room).map_err(CommandError::InvalidName)?;
+        if self.room() == room {
+            return Err(CommandError::Failed(format!("already in {}", room)));
+        }
+        let actions = self.server.enter_room(self.id, &self.nick, room);
+        self.actions.extend(actions);
+        Ok(())
+    }
+
+    /// 按名称排序的房间与成员数量
+    pub fn rooms(&self) -> Vec<(String, usize)> {
+        self.server
+            .rooms
+            .list()
+            .map(|(room, members)| (room.to_string(), members))
+            .collect()
+    }
+
+    /// 房间中的用户昵称，按连接编号排序
+    pub fn members(&self, room: &str) -> Vec<String> {
+        self.server
+            .rooms
+            .members(room)
+            .filter_map(|id| self.server.sessions.get(&id)?.nick.clone())
+            .collect()
+    }
+
+    pub fn topic(&self, room: &str) -> Option<String> {
+        self.server.rooms.topic(room).map(str::to_string)
+    }
+
+    pub fn set_topic(&mut self, room: &str, topic: Option<String>) {
+        self.server.rooms.set_topic(room, topic);
+    }
+
+    /// 向发出命令的用户回放当前房间的历史消息
+    pub fn replay(&mut self, replay: Replay) -> Result<(), CommandError> {
+        let room = self.room();
+        let actions = self.server.replay(self.id, &room, replay).map_err(CommandError::Failed)?;
+        self.actions.extend(actions);
+        Ok(())
+    }
+
+    /// 修改发出命令的用户的昵称
+    pub fn rename(&mut self, nick: &str) -> Result<(), CommandError> {
+        validate_nickname(nick).map_err(CommandError::InvalidName)?;
+        if self.server.find_user(nick).is_some() {
+            return Err(CommandError::NickTaken(nick.to_string()));
+        }
+        self.server.rename(self.id, nick);
+        self.nick = nick.to_string();
+        Ok(())
+    }
+
+    /// 处理完命令后关闭连接
+    pub fn close(&mut self) {
+        self.actions.push(Action::Close { id: self.id });
+    }
+}
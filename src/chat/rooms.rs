@@ -0,0 +1,67 @@
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::ConnId;
+
+/// 默认的房间，完成握手的连接首先加入这个房间，它在没有成员时也不会被删除
+pub const LOBBY: &str = "lobby";
+
+#[derive(Debug, Default)]
+struct Room {
+    members: BTreeSet<ConnId>,
+    topic: Option<String>,
+}
+
+/// 房间与成员的对应关系，没有成员的房间会被删除
+#[derive(Debug)]
+pub(crate) struct Rooms {
+    rooms: BTreeMap<String, Room>,
+}
+
+impl Default for Rooms {
+    fn default() -> Self {
+        let mut rooms = BTreeMap::new();
+        rooms.insert(LOBBY.to_string(), Room::default());
+        Self { rooms }
+    }
+}
+
+impl Rooms {
+    pub(crate) fn join(&mut self, room: &str, id: ConnId) {
+        self.rooms.entry(room.to_string()).or_default().members.insert(id);
+    }
+
+    pub(crate) fn leave(&mut self, room: &str, id: ConnId) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            entry.members.remove(&id);
+            if entry.members.is_empty() && room != LOBBY {
+                self.rooms.remove(room);
+            }
+        }
+    }
+
+    /// 房间中的成员，房间不存在时为空
+    pub(crate) fn members<'a>(&'a self, room: &str) -> impl Iterator<Item = ConnId> + 'a {
+        self.rooms
+            .get(room)
+            .into_iter()
+            .flat_map(|entry| entry.members.iter().copied())
+    }
+
+    /// 按名称排序的房间与成员数量
+    pub(crate) fn list(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.rooms
+            .iter()
+            .map(|(name, entry)| (name.as_str(), entry.members.len()))
+    }
+
+    pub(crate) fn topic(&self, room: &str) -> Option<&str> {
+        self.rooms.get(room).and_then(|entry| entry.topic.as_deref())
+    }
+
+    /// 设置房间的话题，房间被删除时话题一起删除
+    pub(crate) fn set_topic(&mut self, room: &str, topic: Option<String>) {
+        if let Some(entry) = self.rooms.get_mut(room) {
+            entry.topic = topic;
+        }
+    }
+}
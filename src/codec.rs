@@ -0,0 +1,197 @@
+//! 消息帧格式：4 字节大端序的长度后跟消息内容。
+//! `FrameDecoder` 可以接收任意切分的数据，适用于各个后端的非阻塞读取；
+//! `read_frame` 与 `write_frame` 用于阻塞的 socket
+
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fmt;
+use std::io::{self, Read, Write};
+
+/// 长度字段的字节数
+pub const HEADER_LEN: usize = 4;
+
+/// 默认允许的最大消息长度
+pub const MAX_FRAME_LEN: usize = 1 << 20;
+
+/// 解码错误
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FrameError {
+    /// 消息长度超过上限
+    TooLong { len: usize, max: usize },
+}
+
+impl fmt::Display for FrameError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FrameError::TooLong { len, max } => {
+                write!(f, "frame of {} bytes exceeds the limit of {} bytes", len, max)
+            }
+        }
+    }
+}
+
+impl Error for FrameError {}
+
+impl From<FrameError> for io::Error {
+    fn from(err: FrameError) -> Self {
+        io::Error::new(io::ErrorKind::InvalidData, err)
+    }
+}
+
+/// 将消息编码为一帧
+pub fn encode(payload: &[u8]) -> Vec<u8> {
+    let mut frame = Vec::with_capacity(HEADER_LEN + payload.len());
+    encode_into(payload, &mut frame);
+    frame
+}
+
+/// 将消息编码后追加到 buf 末尾
+pub fn encode_into(payload: &[u8], buf: &mut Vec<u8>) {
+    let len = u32::try_from(payload.len()).expect("frame length overflows u32");
+    buf.extend_from_slice(&len.to_be_bytes());
+    buf.extend_from_slice(payload);
+}
+
+/// 增量解码器，缓存不完整的帧直到收到剩余的数据
+#[derive(Debug)]
+pub struct FrameDecoder {
+    buf: Vec<u8>,
+    /// buf 中尚未解码的数据的起始位置
+    start: usize,
+    max_len: usize,
+}
+
+impl Default for FrameDecoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::with_max_len(MAX_FRAME_LEN)
+    }
+
+    pub fn with_max_len(max_len: usize) -> Self {
+        Self {
+            buf: vec![],
+            start: 0,
+            max_len,
+        }
+    }
+
+    /// 放入从连接中读取到的数据
+    pub fn extend(&mut self, data: &[u8]) {
+        // 已解码的数据超过一半时再整体前移，避免每帧都移动缓冲区
+        if self.start > 0 && self.start >= self.buf.len() / 2 {
+            self.buf.drain(..self.start);
+            self.start = 0;
+        }
+        self.buf.extend_from_slice(data);
+    }
+
+    /// 取出下一个完整的帧，数据不足时返回 `Ok(None)`。
+    /// 返回错误后连接中的数据已无法恢复同步，应当关闭连接
+    pub fn next_frame(&mut self) -> Result<Option<Vec<u8>>, FrameError> {
+        let pending = &self.buf[self.start..];
+        if pending.len() < HEADER_LEN {
+            return Ok(None);
+        }
+        let mut header = [0; HEADER_LEN];
+        header.copy_from_slice(&pending[..HEADER_LEN]);
+        let len = u32::from_be_bytes(header) as usize;
+        if len > self.max_len {
+            return Err(FrameError::TooLong { len, max: self.max_len });
+        }
+        if pending.len() < HEADER_LEN + len {
+            return Ok(None);
+        }
+        let frame = pending[HEADER_LEN..HEADER_LEN + len].to_vec();
+        self.start += HEADER_LEN + len;
+        if self.start == self.buf.len() {
+            self.buf.clear();
+            self.start = 0;
+        }
+        Ok(Some(frame))
+    }
+
+    /// 缓存中尚未组成完整帧的字节数
+    pub fn buffered(&self) -> usize {
+        self.buf.len() - self.start
+    }
+}
+
+/// 从阻塞的 reader 中读取一帧，对端在帧边界处关闭连接时返回 `Ok(None)`
+pub fn read_frame<R: Read>(reader: &mut R) -> io::Result<Option<Vec<u8>>> {
+    let mut header = [0; HEADER_LEN];
+    let mut read = 0;
+    while read < HEADER_LEN {
+        match reader.read(&mut header[read..]) {
+            Ok(0) if read == 0 => return Ok(None),
+            Ok(0) => return Err(io::ErrorKind::UnexpectedEof.into()),
+            Ok(n) => read += n,
+            Err(ref err) if err.kind() == io::ErrorKind::Interrupted => continue,
+            Err(err) => return Err(err),
+        }
+    }
+    let len = u32::from_be_bytes(header) as usize;
+    if len > MAX_FRAME_LEN {
+        return Err(FrameError::TooLong { len, max: MAX_FRAME_LEN }.into());
+    }
+    let mut payload = vec![0; len];
+    reader.read_exact(&mut payload)?;
+    Ok(Some(payload))
+}
+
+/// 向阻塞的 writer 写入一帧
+pub fn write_frame<W: Write>(writer: &mut W, payload: &[u8]) -> io::Result<()> {
+    writer.write_all(&encode(payload))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn decoder_handles_arbitrary_splits() {
+        let mut stream = encode(b"hello");
+        encode_into(b"", &mut stream);
+        encode_into(&[7; 300], &mut stream);
+        for chunk in 1..=stream.len() {
+            let mut decoder = FrameDecoder::new();
+            let mut frames = vec![];
+            for data in stream.chunks(chunk) {
+                decoder.extend(data);
+                while let Some(frame) = decoder.next_frame().unwrap() {
+                    frames.push(frame);
+                }
+            }
+            assert_eq!(frames, vec![b"hello".to_vec(), vec![], vec![7; 300]], "chunk {}", chunk);
+            assert_eq!(decoder.buffered(), 0);
+        }
+    }
+
+    #[test]
+    fn decoder_rejects_long_frames() {
+        let mut decoder = FrameDecoder::with_max_len(4);
+        decoder.extend(&encode(b"12345"));
+        assert_eq!(decoder.next_frame(), Err(FrameError::TooLong { len: 5, max: 4 }));
+    }
+
+    #[test]
+    fn read_frame_stops_at_frame_boundary() {
+        let mut stream = vec![];
+        write_frame(&mut stream, b"one").unwrap();
+        write_frame(&mut stream, b"two").unwrap();
+        let mut reader = Cursor::new(&stream);
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"one".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), Some(b"two".to_vec()));
+        assert_eq!(read_frame(&mut reader).unwrap(), None);
+
+        let mut truncated = Cursor::new(&stream[..5]);
+        let err = read_frame(&mut truncated).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,306 @@
+//! `labs-net` 的运行参数，可以来自命令行或 TOML 配置文件，命令行中的参数优先
+
+use crate::affinity::AffinityArgs;
+use crate::backend::{SlowConsumerPolicy, DEFAULT_CAPACITY, DEFAULT_PAUSE_TIMEOUT};
+
+use std::env;
+use std::fmt;
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+pub const USAGE: &str = "\
+Usage: labs-net [OPTIONS]
+
+Options:
+    --config <path>           TOML config file, keys are the option names below
+    --backend <name>          threadpool | mio | epoll | tokio | iouring [default: threadpool]
+    --address <ip>            bind address [default: 127.0.0.1]
+    --port <port>             bind port [default: 8080]
+    --workers <n>             worker threads [default: 16]
+    --buffer-size <bytes>     read buffer size per connection [default: backend specific]
+    --ring-depth <n>          io_uring submission queue depth [default: 256]
+    --max-connections <n>     reject connections beyond this limit [default: unlimited]
+    --outbound-capacity <n>   frames queued per connection before it is a slow consumer [default: 1024]
+    --slow-consumer <policy>  drop-oldest | disconnect | pause [default: drop-oldest]
+    --pause-timeout <secs>    close a slow consumer that keeps producers paused this long [default: 10]
+    --history-dir <path>      persist room messages in this directory [default: disabled]
+    --history-segment-size <bytes>
+                              start a new history segment file beyond this size [default: 16777216]
+    --worker-cores <list>     pin worker threads to these cores, e.g. 0-3,8
+    --numa-node <node>        pin worker threads to the cores of a NUMA node
+    --reactor-core <core>     pin the event loop thread to this core
+    -h, --help                print this message";
+
+/// I/O 后端
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BackendKind {
+    ThreadPool,
+    Mio,
+    Epoll,
+    Tokio,
+    IoUring,
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "threadpool" => Ok(BackendKind::ThreadPool),
+            "mio" => Ok(BackendKind::Mio),
+            "epoll" => Ok(BackendKind::Epoll),
+            "tokio" => Ok(BackendKind::Tokio),
+            "iouring" => Ok(BackendKind::IoUring),
+            _ => Err(format!("unknown backend `{}`", s)),
+        }
+    }
+}
+
+impl fmt::Display for BackendKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            BackendKind::ThreadPool => "threadpool",
+            BackendKind::Mio => "mio",
+            BackendKind::Epoll => "epoll",
+            BackendKind::Tokio => "tokio",
+            BackendKind::IoUring => "iouring",
+        };
+        f.write_str(name)
+    }
+}
+
+/// 服务器的运行参数
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Config {
+    pub backend: BackendKind,
+    pub address: IpAddr,
+    pub port: u16,
+    /// 线程池或 tokio 运行时的工作线程数
+    pub workers: u32,
+    /// 每次读取的缓冲区大小，`None` 时使用后端的默认值
+    pub buffer_size: Option<usize>,
+    /// io_uring 提交队列的深度
+    pub ring_depth: u32,
+    /// 同时存在的最大连接数，`None` 时不限制
+    pub max_connections: Option<usize>,
+    /// 每个连接的发送队列最多排队的帧数，线程池后端没有发送队列
+    pub outbound_capacity: usize,
+    /// 发送队列已满时的处理方式
+    pub slow_consumer: SlowConsumerPolicy,
+    /// `pause` 策略下生产者最多被暂停的秒数
+    pub pause_timeout: u64,
+    /// 消息记录的目录，`None` 时不持久化消息
+    pub history_dir: Option<PathBuf>,
+    /// 消息记录中段文件的大小上限，`None` 时使用默认值
+    pub history_segment_size: Option<u64>,
+    pub affinity: AffinityArgs,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            backend: BackendKind::ThreadPool,
+            address: IpAddr::V4(Ipv4Addr::LOCALHOST),
+            port: 8080,
+            workers: 16,
+            buffer_size: None,
+            ring_depth: 256,
+            max_connections: None,
+            outbound_capacity: DEFAULT_CAPACITY,
+            slow_consumer: SlowConsumerPolicy::default(),
+            pause_timeout: DEFAULT_PAUSE_TIMEOUT.as_secs(),
+            history_dir: None,
+            history_segment_size: None,
+            affinity: AffinityArgs::default(),
+        }
+    }
+}
+
+impl Config {
+    /// 从命令行参数中解析配置，`--help` 时打印用法并退出，参数不合法时打印错误并退出
+    pub fn from_env() -> Self {
+        let args: Vec<String> = env::args().skip(1).collect();
+        if args.iter().any(|arg| arg == "-h" || arg == "--help") {
+            println!("{}", USAGE);
+            std::process::exit(0);
+        }
+        match Self::parse(args) {
+            Ok(config) => config,
+            Err(err) => {
+                eprintln!("{}\n\n{}", err, USAGE);
+                std::process::exit(2);
+            }
+        }
+    }
+
+    /// 解析 `--<key> <value>` 形式的参数，先读取 `--config` 指定的文件，再用其余参数覆盖
+    pub fn parse<I: IntoIterator<Item = String>>(args: I) -> Result<Self, String> {
+        let args: Vec<String> = args.into_iter().collect();
+        let mut config = Self::default();
+        if let Some(pos) = args.iter().position(|arg| arg == "--config") {
+            let path = args.get(pos + 1).ok_or("missing value for --config")?;
+            config.load(path)?;
+        }
+
+        let mut args = args.iter();
+        while let Some(arg) = args.next() {
+            let key = arg.strip_prefix("--").unwrap_or_default();
+            let value = args.next().ok_or(format!("missing value for {}", arg))?;
+            if key != "config" && !config.set(key, value)? {
+                return Err(format!("unknown argument `{}`", arg));
+            }
+        }
+        Ok(config)
+    }
+
+    /// 读取 TOML 配置文件，键名与命令行参数相同，`-` 也可以写作 `_`
+    pub fn load(&mut self, path: &str) -> Result<(), String> {
+        let content = fs::read_to_string(path).map_err(|err| format!("fail to read {}: {}", path, err))?;
+        let table = match content.parse::<toml::Value>() {
+            Ok(toml::Value::Table(table)) => table,
+            Ok(_) => return Err(format!("{} is not a TOML table", path)),
+            Err(err) => return Err(format!("fail to parse {}: {}", path, err)),
+        };
+        for (key, value) in table {
+            let value = match value {
+                toml::Value::String(s) => s,
+                toml::Value::Integer(n) => n.to_string(),
+                // 核心列表也可以写成整数数组
+                toml::Value::Array(items) if items.iter().all(toml::Value::is_integer) => items
+                    .iter()
+                    .map(|item| item.to_string())
+                    .collect::<Vec<_>>()
+                    .join(","),
+                _ => return Err(format!("invalid value for `{}` in {}", key, path)),
+            };
+            if !self.set(&key.replace('_', "-"), &value)? {
+                return Err(format!("unknown key `{}` in {}", key, path));
+            }
+        }
+        Ok(())
+    }
+
+    /// 设置一个参数，key 不带 `--` 前缀，未知参数返回 `Ok(false)`
+    pub fn set(&mut self, key: &str, value: &str) -> Result<bool, String> {
+        match key {
+            "backend" => self.backend = value.parse()?,
+            "address" => {
+                self.address = value
+                    .parse()
+                    .map_err(|_| format!("invalid address `{}`", value))?
+            }
+            "port" => {
+                self.port = value
+                    .parse()
+                    .map_err(|_| format!("invalid port `{}`", value))?
+            }
+            "workers" => self.workers = parse_positive(key, value)?,
+            "buffer-size" => self.buffer_size = Some(parse_positive(key, value)?),
+            "ring-depth" => self.ring_depth = parse_positive(key, value)?,
+            "max-connections" => self.max_connections = Some(parse_positive(key, value)?),
+            "outbound-capacity" => self.outbound_capacity = parse_positive(key, value)?,
+            "slow-consumer" => self.slow_consumer = value.parse()?,
+            "pause-timeout" => self.pause_timeout = parse_positive(key, value)?,
+            "history-dir" => self.history_dir = Some(PathBuf::from(value)),
+            "history-segment-size" => self.history_segment_size = Some(parse_positive(key, value)?),
+            _ => return self.affinity.set(key, value),
+        }
+        Ok(true)
+    }
+
+    /// 服务器监听的地址
+    pub fn addr(&self) -> SocketAddr {
+        SocketAddr::new(self.address, self.port)
+    }
+}
+
+fn parse_positive<T: FromStr + Default + PartialEq>(key: &str, value: &str) -> Result<T, String> {
+    match value.parse::<T>() {
+        Ok(n) if n != T::default() => Ok(n),
+        _ => Err(format!("{} must be a positive integer, got `{}`", key, value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 将内容写入每个测试自己的配置文件，返回其路径
+    fn config_file(name: &str, content: &str) -> String {
+        let path = env::temp_dir().join(format!("labs-net-config-{}-{}.toml", name, std::process::id()));
+        fs::write(&path, content).unwrap();
+        path.to_str().unwrap().to_owned()
+    }
+
+    fn parse(args: &[&str]) -> Result<Config, String> {
+        Config::parse(args.iter().map(|arg| arg.to_string()))
+    }
+
+    #[test]
+    fn defaults_without_arguments() {
+        assert_eq!(parse(&[]).unwrap(), Config::default());
+    }
+
+    #[test]
+    fn command_line_overrides_file_overrides_defaults() {
+        let path = config_file(
+            "precedence",
+            "backend = \"mio\"\nport = 9000\nworkers = 4\nslow_consumer = \"pause\"\nworker-cores = [0, 1]\n",
+        );
+        // `--config` 的位置不影响优先级
+        let config = parse(&["--port", "9100", "--config", &path, "--workers", "8"]).unwrap();
+        assert_eq!(config.backend, BackendKind::Mio);
+        assert_eq!(config.port, 9100);
+        assert_eq!(config.workers, 8);
+        assert_eq!(config.slow_consumer, SlowConsumerPolicy::Pause);
+        assert_eq!(config.affinity.worker_cores, Some(vec![0, 1]));
+        assert_eq!(config.address, Config::default().address);
+        assert_eq!(config.pause_timeout, Config::default().pause_timeout);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unknown_keys_are_rejected() {
+        let err = parse(&["--threads", "4"]).unwrap_err();
+        assert!(err.contains("unknown argument `--threads`"), "{}", err);
+        let err = parse(&["port", "4"]).unwrap_err();
+        assert!(err.contains("unknown argument `port`"), "{}", err);
+
+        let path = config_file("unknown", "threads = 4\n");
+        let err = parse(&["--config", &path]).unwrap_err();
+        assert!(err.contains("unknown key `threads`"), "{}", err);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn bad_values_are_rejected() {
+        for args in [
+            &["--backend", "select"][..],
+            &["--address", "localhost"],
+            &["--port", "65536"],
+            &["--workers", "0"],
+            &["--workers", "-1"],
+            &["--slow-consumer", "block"],
+            &["--pause-timeout", "soon"],
+            &["--worker-cores", "3-1"],
+            &["--port"],
+            &["--config"],
+        ] {
+            assert!(parse(args).is_err(), "{:?} should be rejected", args);
+        }
+
+        let path = config_file("bad-value", "port = true\n");
+        let err = parse(&["--config", &path]).unwrap_err();
+        assert!(err.contains("invalid value for `port`"), "{}", err);
+        fs::remove_file(path).unwrap();
+
+        let path = config_file("bad-syntax", "port = \n");
+        assert!(parse(&["--config", &path]).unwrap_err().starts_with("fail to parse"));
+        fs::remove_file(path).unwrap();
+
+        let err = parse(&["--config", "/nonexistent/labs-net.toml"]).unwrap_err();
+        assert!(err.starts_with("fail to read"), "{}", err);
+    }
+}
@@ -0,0 +1,340 @@
+//! 持久化的消息记录：房间中的消息按顺序追加写入目录中的段文件。
+//! 段文件以其中第一条消息的序号命名（例如 `00000000000000000042.log`），
+//! 超过 `segment_size` 字节后创建新的段文件。
+//! 每条记录是一帧 `codec` 格式的二进制 `Envelope`。
+//!
+//! 打开时扫描所有段文件，在内存中建立按房间与序号查找记录位置的索引。
+//! 最后一个段文件末尾不完整的记录（例如写入时进程退出）会被截掉。
+//! 写入后不调用 `fsync`，系统崩溃时可能丢失最近的消息
+//!
+//! 服务器发出的消息中只有一部分会被记录，但所有消息都会占用序号，
+//! 所以已分配的序号上限单独保存在 `next_seq` 文件中，每次预留 `SEQ_BLOCK` 个序号。
+//! 重启后从上限继续编号，上次没有用完的序号被跳过
+
+use crate::codec::{self, read_frame};
+use crate::protocol::Envelope;
+
+use std::collections::HashMap;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// 段文件的默认大小上限
+pub const DEFAULT_SEGMENT_SIZE: u64 = 16 << 20;
+
+const SEGMENT_SUFFIX: &str = ".log";
+
+/// 保存已预留的序号上限的文件
+const SEQ_FILE: &str = "next_seq";
+
+/// 每次预留的序号数量
+const SEQ_BLOCK: u64 = 1024;
+
+/// 一条记录在段文件中的位置
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    seq: u64,
+    /// 段文件的编号，即段中第一条消息的序号
+    segment: u64,
+    offset: u64,
+}
+
+/// 只追加的消息记录
+#[derive(Debug)]
+pub struct MessageLog {
+    dir: PathBuf,
+    segment_size: u64,
+    /// 正在写入的段文件的编号与文件
+    active: Option<(u64, File)>,
+    /// 正在写入的段文件的长度
+    active_len: u64,
+    /// 每个房间的记录位置，按序号递增
+    index: HashMap<String, Vec<Position>>,
+    last_seq: Option<u64>,
+    /// 已持久化的序号上限，小于它的序号可以直接分配
+    reserved: u64,
+}
+
+impl MessageLog {
+    /// 打开目录中的消息记录，目录不存在时创建
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        fs::create_dir_all(&dir)?;
+        let mut segments = vec![];
+        for entry in fs::read_dir(&dir)? {
+            let name = entry?.file_name();
+            let segment = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(SEGMENT_SUFFIX))
+                .and_then(|stem| stem.parse::<u64>().ok());
+            if let Some(segment) = segment {
+                segments.push(segment);
+            }
+        }
+        segments.sort_unstable();
+
+        let mut log = Self {
+            dir,
+            segment_size: DEFAULT_SEGMENT_SIZE,
+            active: None,
+            active_len: 0,
+            index: HashMap::new(),
+            last_seq: None,
+            reserved: 0,
+        };
+        log.reserved = log.read_reserved()?;
+        for (i, &segment) in segments.iter().enumerate() {
+            let is_last = i + 1 == segments.len();
+            log.scan(segment, is_last)?;
+        }
+        Ok(log)
+    }
+
+    /// 单个段文件的大小上限，超过后下一条消息写入新的段文件
+    pub fn segment_size(mut self, bytes: u64) -> Self {
+        self.segment_size = bytes;
+        self
+    }
+
+    /// 下一条消息应当使用的序号，服务器重启后从这里继续编号
+    pub fn next_seq(&self) -> u64 {
+        self.last_seq.map_or(0, |seq| seq + 1).max(self.reserved)
+    }
+
+    /// 在分配序号 seq 之前调用，保证重启后不会再次分配它，
+    /// 包括不会被记录的消息的序号
+    pub fn reserve(&mut self, seq: u64) -> io::Result<()> {
+        if seq < self.reserved {
+            return Ok(());
+        }
+        let reserved = seq.saturating_add(SEQ_BLOCK);
+        // 先写入临时文件再替换，进程在写入时退出也不会留下不完整的上限
+        let tmp = self.dir.join(format!("{}.tmp", SEQ_FILE));
+        fs::write(&tmp, reserved.to_string())?;
+        fs::rename(&tmp, self.dir.join(SEQ_FILE))?;
+        self.reserved = reserved;
+        Ok(())
+    }
+
+    /// 追加一条消息，没有房间的消息不会被记录
+    pub fn append(&mut self, envelope: &Envelope) -> io::Result<()> {
+        let room = match &envelope.room {
+            Some(room) => room,
+            None => return Ok(()),
+        };
+        let record = codec::encode(&envelope.to_binary());
+        let rotate = match self.active {
+            Some(_) => self.active_len > 0 && self.active_len + record.len() as u64 > self.segment_size,
+            None => true,
+        };
+        if rotate {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(self.segment_path(envelope.seq))?;
+            self.active_len = file.metadata()?.len();
+            self.active = Some((envelope.seq, file));
+        }
+
+        let (segment, file) = self.active.as_mut().unwrap();
+        file.write_all(&record)?;
+        self.index.entry(room.clone()).or_default().push(Position {
+            seq: envelope.seq,
+            segment: *segment,
+            offset: self.active_len,
+        });
+        self.active_len += record.len() as u64;
+        self.last_seq = Some(envelope.seq);
+        Ok(())
+    }
+
+    /// 房间中最近的 n 条消息，按序号递增
+    pub fn last(&self, room: &str, n: usize) -> io::Result<Vec<Envelope>> {
+        let positions = self.positions(room);
+        self.read(&positions[positions.len().saturating_sub(n)..])
+    }
+
+    /// 房间中序号大于 seq 的消息，最多 limit 条，按序号递增
+    pub fn since(&self, room: &str, seq: u64, limit: usize) -> io::Result<Vec<Envelope>> {
+        let positions = self.positions(room);
+        let start = positions.partition_point(|position| position.seq <= seq);
+        let end = positions.len().min(start.saturating_add(limit));
+        self.read(&positions[start..end])
+    }
+
+    fn positions(&self, room: &str) -> &[Position] {
+        self.index.get(room).map_or(&[], Vec::as_slice)
+    }
+
+    /// 读取已预留的序号上限，文件不存在时为 0
+    fn read_reserved(&self) -> io::Result<u64> {
+        let path = self.dir.join(SEQ_FILE);
+        match fs::read_to_string(&path) {
+            Ok(content) => content.trim().parse().map_err(|err| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("{}: {}", path.display(), err))
+            }),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => Ok(0),
+            Err(err) => Err(err),
+        }
+    }
+
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.dir.join(format!("{:020}{}", segment, SEGMENT_SUFFIX))
+    }
+
+    /// 按位置读取记录，同一个段文件中的记录只打开一次文件
+    fn read(&self, positions: &[Position]) -> io::Result<Vec<Envelope>> {
+        let mut envelopes = Vec::with_capacity(positions.len());
+        let mut reader: Option<(u64, BufReader<File>)> = None;
+        for position in positions {
+            let file = match &mut reader {
+                Some((segment, file)) if *segment == position.segment => file,
+                _ => {
+                    let file = File::open(self.segment_path(position.segment))?;
+                    &mut reader.insert((position.segment, BufReader::new(file))).1
+                }
+            };
+            file.seek(SeekFrom::Start(position.offset))?;
+            let frame = read_frame(file)?.ok_or(io::ErrorKind::UnexpectedEof)?;
+            envelopes.push(decode(&frame)?);
+        }
+        Ok(envelopes)
+    }
+
+    /// 扫描段文件并建立索引，最后一个段文件作为继续写入的段文件
+    fn scan(&mut self, segment: u64, is_last: bool) -> io::Result<()> {
+        let path = self.segment_path(segment);
+        let mut reader = BufReader::new(File::open(&path)?);
+        let mut offset = 0;
+        loop {
+            match read_frame(&mut reader) {
+                Ok(Some(frame)) => {
+                    let envelope = decode(&frame).map_err(|err| {
+                        io::Error::new(err.kind(), format!("{} at offset {}: {}", path.display(), offset, err))
+                    })?;
+                    if let Some(room) = envelope.room {
+                        self.index.entry(room).or_default().push(Position {
+                            seq: envelope.seq,
+                            segment,
+                            offset,
+                        });
+                    }
+                    self.last_seq = Some(envelope.seq);
+                    offset += (codec::HEADER_LEN + frame.len()) as u64;
+                }
+                Ok(None) => break,
+                Err(err) if err.kind() == io::ErrorKind::UnexpectedEof && is_last => {
+                    eprintln!(
+                        "[Error] Truncate incomplete record at offset {} of {}",
+                        offset,
+                        path.display()
+                    );
+                    break;
+                }
+                Err(err) => return Err(io::Error::new(err.kind(), format!("{}: {}", path.display(), err))),
+            }
+        }
+
+        if is_last {
+            let file = OpenOptions::new().append(true).open(&path)?;
+            file.set_len(offset)?;
+            self.active = Some((segment, file));
+            self.active_len = offset;
+        }
+        Ok(())
+    }
+}
+
+fn decode(frame: &[u8]) -> io::Result<Envelope> {
+    Envelope::from_binary(frame).map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::Kind;
+
+    /// 每个测试使用自己的空目录
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("labs-net-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        dir
+    }
+
+    fn message(seq: u64, room: &str, body: &str) -> Envelope {
+        let mut envelope = Envelope::chat(body).from_sender(1, "alice").in_room(room);
+        envelope.seq = seq;
+        envelope
+    }
+
+    fn bodies(envelopes: &[Envelope]) -> Vec<&str> {
+        envelopes.iter().map(|envelope| envelope.body.as_str()).collect()
+    }
+
+    #[test]
+    fn last_and_since_across_segments() {
+        let dir = temp_dir("segments");
+        let mut log = MessageLog::open(&dir).unwrap().segment_size(64);
+        for seq in 0..10 {
+            let room = if seq % 2 == 0 { "lobby" } else { "rust" };
+            log.append(&message(seq, room, &seq.to_string())).unwrap();
+        }
+        log.append(&Envelope::new(Kind::System, "no room")).unwrap();
+        assert!(fs::read_dir(&dir).unwrap().count() > 2);
+
+        assert_eq!(bodies(&log.last("lobby", 3).unwrap()), ["4", "6", "8"]);
+        assert_eq!(bodies(&log.since("rust", 3, 2).unwrap()), ["5", "7"]);
+        assert_eq!(log.since("rust", 9, 10).unwrap(), vec![]);
+        assert_eq!(log.last("nowhere", 10).unwrap(), vec![]);
+
+        let log = MessageLog::open(&dir).unwrap();
+        assert_eq!(bodies(&log.last("rust", 10).unwrap()), ["1", "3", "5", "7", "9"]);
+        assert_eq!(log.next_seq(), 10);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn reserved_sequence_numbers_survive_restart() {
+        let dir = temp_dir("reserve");
+        let mut log = MessageLog::open(&dir).unwrap();
+        assert_eq!(log.next_seq(), 0);
+        // 序号 0、1 与 3 属于不会被记录的消息
+        for seq in 0..4 {
+            log.reserve(seq).unwrap();
+        }
+        log.append(&message(2, "lobby", "logged")).unwrap();
+        drop(log);
+
+        let mut log = MessageLog::open(&dir).unwrap();
+        let next = log.next_seq();
+        assert!(next > 3);
+        log.reserve(next).unwrap();
+        log.append(&message(next, "lobby", "after restart")).unwrap();
+        drop(log);
+
+        let log = MessageLog::open(&dir).unwrap();
+        assert!(log.next_seq() > next);
+        let seqs: Vec<u64> = log.since("lobby", 0, 10).unwrap().iter().map(|envelope| envelope.seq).collect();
+        assert_eq!(seqs, [2, next]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn incomplete_tail_is_truncated() {
+        let dir = temp_dir("truncate");
+        let mut log = MessageLog::open(&dir).unwrap();
+        log.append(&message(0, "lobby", "complete")).unwrap();
+        drop(log);
+        let segment = dir.join(format!("{:020}{}", 0, SEGMENT_SUFFIX));
+        let mut file = OpenOptions::new().append(true).open(&segment).unwrap();
+        let record = codec::encode(&message(1, "lobby", "partial").to_binary());
+        file.write_all(&record[..record.len() - 1]).unwrap();
+        drop(file);
+
+        let mut log = MessageLog::open(&dir).unwrap();
+        assert_eq!(log.next_seq(), 1);
+        log.append(&message(1, "lobby", "rewritten")).unwrap();
+        assert_eq!(bodies(&log.last("lobby", 10).unwrap()), ["complete", "rewritten"]);
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,3 +1,10 @@
 #![feature(async_closure)]
 
-pub mod tp;
\ No newline at end of file
+pub mod affinity;
+pub mod backend;
+pub mod chat;
+pub mod codec;
+pub mod config;
+pub mod history;
+pub mod protocol;
+pub mod tp;
@@ -0,0 +1,422 @@
+//! 聊天协议：每一帧的内容是一个 `Envelope`，可以使用二进制或 JSON 两种格式。
+//! JSON 格式以 `{` 开头，二进制格式以消息类型的编号开头，服务器据此区分两种格式，
+//! 并使用客户端最近一次发送的格式回复
+//!
+//! 二进制格式（整数均为大端序）：
+//!
+//! | 类型 u8 | 序号 u64 | 时间戳 u64 | 是否有发送者 u8 | 发送者 u64 | 昵称长度 u8 | 昵称 | 房间长度 u8 | 房间 | 内容（UTF-8，直到帧结束） |
+//!
+//! 连接建立后客户端首先发送 `Hello`，nick 为想要使用的昵称，内容为协议版本号，
+//! 版本号之后可以跟随 `last <n>` 或 `since <seq>` 请求回放大厅中的历史消息。
+//! 服务器接受后回复 `System` 消息，拒绝时回复 `Error` 消息说明原因并关闭连接
+
+use serde::{Deserialize, Serialize};
+
+use std::convert::TryInto;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// 当前的协议版本
+pub const PROTOCOL_VERSION: u32 = 1;
+
+/// 二进制格式中固定长度部分的长度
+const BINARY_HEADER_LEN: usize = 1 + 8 + 8 + 1 + 8;
+
+/// 消息类型
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Kind {
+    /// 用户发送的聊天消息
+    Chat,
+    /// 有用户加入
+    Join,
+    /// 有用户离开
+    Leave,
+    /// 服务器发送的通知
+    System,
+    /// 服务器拒绝了客户端的请求
+    Error,
+    /// 客户端的握手请求
+    Hello,
+    /// 只发给一个用户的私信
+    Direct,
+}
+
+impl Kind {
+    fn to_u8(self) -> u8 {
+        match self {
+            Kind::Chat => 0,
+            Kind::Join => 1,
+            Kind::Leave => 2,
+            Kind::System => 3,
+            Kind::Error => 4,
+            Kind::Hello => 5,
+            Kind::Direct => 6,
+        }
+    }
+
+    fn from_u8(n: u8) -> Option<Self> {
+        match n {
+            0 => Some(Kind::Chat),
+            1 => Some(Kind::Join),
+            2 => Some(Kind::Leave),
+            3 => Some(Kind::System),
+            4 => Some(Kind::Error),
+            5 => Some(Kind::Hello),
+            6 => Some(Kind::Direct),
+            _ => None,
+        }
+    }
+}
+
+/// 序列化格式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash)]
+pub enum Format {
+    #[default]
+    Binary,
+    Json,
+}
+
+/// 消息信封
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Envelope {
+    pub kind: Kind,
+    /// 发送者的连接编号，服务器发出的消息没有发送者
+    #[serde(default)]
+    pub sender: Option<u64>,
+    /// 发送者的昵称，`Hello` 中为客户端想要使用的昵称
+    #[serde(default)]
+    pub nick: Option<String>,
+    /// 消息所属的房间
+    #[serde(default)]
+    pub room: Option<String>,
+    /// 服务器处理消息时的 Unix 时间戳，单位为毫秒
+    #[serde(default)]
+    pub timestamp: u64,
+    /// 服务器分配的序号，按发出的顺序递增
+    #[serde(default)]
+    pub seq: u64,
+    #[serde(default)]
+    pub body: String,
+}
+
+impl Envelope {
+    /// 创建消息，序号与时间戳由服务器在发出时填写
+    pub fn new(kind: Kind, body: impl Into<String>) -> Self {
+        Self {
+            kind,
+            sender: None,
+            nick: None,
+            room: None,
+            timestamp: 0,
+            seq: 0,
+            body: body.into(),
+        }
+    }
+
+    /// 客户端发送的聊天消息
+    pub fn chat(body: impl Into<String>) -> Self {
+        Self::new(Kind::Chat, body)
+    }
+
+    /// 客户端的握手请求
+    pub fn hello(nick: impl Into<String>) -> Self {
+        Self {
+            nick: Some(nick.into()),
+            ..Self::new(Kind::Hello, PROTOCOL_VERSION.to_string())
+        }
+    }
+
+    /// 在握手请求中附带历史消息的回放请求
+    pub fn with_replay(mut self, replay: Replay) -> Self {
+        self.body = format!("{} {}", self.body, replay);
+        self
+    }
+
+    /// 设置发送者
+    pub fn from_sender(mut self, sender: u64, nick: impl Into<String>) -> Self {
+        self.sender = Some(sender);
+        self.nick = Some(nick.into());
+        self
+    }
+
+    /// 设置消息所属的房间
+    pub fn in_room(mut self, room: impl Into<String>) -> Self {
+        self.room = Some(room.into());
+        self
+    }
+
+    pub fn encode(&self, format: Format) -> Vec<u8> {
+        match format {
+            Format::Binary => self.to_binary(),
+            Format::Json => self.to_json(),
+        }
+    }
+
+    /// 按照第一个字节判断格式并解码，同时返回使用的格式
+    pub fn decode(data: &[u8]) -> Result<(Self, Format), ProtocolError> {
+        match data.first() {
+            None => Err(ProtocolError::Empty),
+            Some(b'{') => Self::from_json(data).map(|envelope| (envelope, Format::Json)),
+            Some(_) => Self::from_binary(data).map(|envelope| (envelope, Format::Binary)),
+        }
+    }
+
+    pub fn to_binary(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(BINARY_HEADER_LEN + 2 + self.body.len());
+        buf.push(self.kind.to_u8());
+        buf.extend_from_slice(&self.seq.to_be_bytes());
+        buf.extend_from_slice(&self.timestamp.to_be_bytes());
+        buf.push(self.sender.is_some() as u8);
+        buf.extend_from_slice(&self.sender.unwrap_or_default().to_be_bytes());
+        push_short_str(&mut buf, self.nick.as_deref());
+        push_short_str(&mut buf, self.room.as_deref());
+        buf.extend_from_slice(self.body.as_bytes());
+        buf
+    }
+
+    pub fn from_binary(data: &[u8]) -> Result<Self, ProtocolError> {
+        if data.len() < BINARY_HEADER_LEN {
+            return Err(ProtocolError::Truncated);
+        }
+        let u64_at = |offset: usize| u64::from_be_bytes(data[offset..offset + 8].try_into().unwrap());
+        let kind = Kind::from_u8(data[0]).ok_or(ProtocolError::UnknownKind(data[0]))?;
+        let sender = match data[17] {
+            0 => None,
+            _ => Some(u64_at(18)),
+        };
+        let mut rest = &data[BINARY_HEADER_LEN..];
+        let nick = take_short_str(&mut rest)?;
+        let room = take_short_str(&mut rest)?;
+        let body = String::from_utf8(rest.to_vec()).map_err(|_| ProtocolError::InvalidUtf8)?;
+        Ok(Self {
+            kind,
+            sender,
+            nick,
+            room,
+            timestamp: u64_at(9),
+            seq: u64_at(1),
+            body,
+        })
+    }
+
+    pub fn to_json(&self) -> Vec<u8> {
+        serde_json::to_vec(self).expect("Fail to serialize envelope")
+    }
+
+    pub fn from_json(data: &[u8]) -> Result<Self, ProtocolError> {
+        serde_json::from_slice(data).map_err(|err| ProtocolError::Json(err.to_string()))
+    }
+}
+
+impl fmt::Display for Envelope {
+    /// 以 `[HH:MM:SS] #seq [房间] <发送者> 内容` 的形式显示，时间为 UTC
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let secs = self.timestamp / 1000;
+        write!(
+            f,
+            "[{:02}:{:02}:{:02}] #{} ",
+            secs / 3600 % 24,
+            secs / 60 % 60,
+            secs % 60,
+            self.seq
+        )?;
+        if let Some(room) = &self.room {
+            write!(f, "[{}] ", room)?;
+        }
+        match (self.kind, &self.nick, self.sender) {
+            (Kind::Chat, Some(nick), _) => write!(f, "<{}> {}", nick, self.body),
+            (Kind::Chat, None, Some(sender)) => write!(f, "<{}> {}", sender, self.body),
+            (Kind::Direct, Some(nick), _) => write!(f, "(direct) <{}> {}", nick, self.body),
+            (Kind::Join, ..) | (Kind::Leave, ..) => write!(f, "* {}", self.body),
+            (Kind::Error, ..) => write!(f, "[Error] {}", self.body),
+            _ => write!(f, "{}", self.body),
+        }
+    }
+}
+
+/// 历史消息的回放请求
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Replay {
+    /// 最近的 n 条消息
+    Last(usize),
+    /// 序号大于 seq 的所有消息，用于断线重连
+    Since(u64),
+}
+
+impl FromStr for Replay {
+    type Err = String;
+
+    /// 解析 `last <n>` 或 `since <seq>`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid replay request `{}`, expected `last <n>` or `since <seq>`", s);
+        let (what, n) = s.trim().split_once(char::is_whitespace).ok_or_else(invalid)?;
+        let n = n.trim().parse::<u64>().map_err(|_| invalid())?;
+        match what {
+            "last" => Ok(Replay::Last(n as usize)),
+            "since" => Ok(Replay::Since(n)),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl fmt::Display for Replay {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Replay::Last(n) => write!(f, "last {}", n),
+            Replay::Since(seq) => write!(f, "since {}", seq),
+        }
+    }
+}
+
+/// 写入一个字节的长度与字符串，空字符串表示没有值，超过 255 字节的部分被截断
+fn push_short_str(buf: &mut Vec<u8>, s: Option<&str>) {
+    let bytes = s.unwrap_or_default().as_bytes();
+    let bytes = &bytes[..bytes.len().min(u8::MAX as usize)];
+    buf.push(bytes.len() as u8);
+    buf.extend_from_slice(bytes);
+}
+
+/// 读取 `push_short_str` 写入的字符串
+fn take_short_str(data: &mut &[u8]) -> Result<Option<String>, ProtocolError> {
+    let (&len, rest) = data.split_first().ok_or(ProtocolError::Truncated)?;
+    let len = len as usize;
+    if rest.len() < len {
+        return Err(ProtocolError::Truncated);
+    }
+    let (s, rest) = rest.split_at(len);
+    *data = rest;
+    match len {
+        0 => Ok(None),
+        _ => String::from_utf8(s.to_vec()).map(Some).map_err(|_| ProtocolError::InvalidUtf8),
+    }
+}
+
+/// 当前的 Unix 时间戳，单位为毫秒
+pub fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_millis() as u64)
+}
+
+/// 解码错误
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ProtocolError {
+    /// 帧的内容为空
+    Empty,
+    /// 二进制消息短于固定头部
+    Truncated,
+    /// 未知的消息类型编号
+    UnknownKind(u8),
+    /// 内容不是合法的 UTF-8
+    InvalidUtf8,
+    /// JSON 格式错误
+    Json(String),
+}
+
+impl fmt::Display for ProtocolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProtocolError::Empty => write!(f, "empty message"),
+            ProtocolError::Truncated => write!(f, "truncated binary message"),
+            ProtocolError::UnknownKind(kind) => write!(f, "unknown message kind {}", kind),
+            ProtocolError::InvalidUtf8 => write!(f, "message body is not valid UTF-8"),
+            ProtocolError::Json(err) => write!(f, "invalid JSON message: {}", err),
+        }
+    }
+}
+
+impl Error for ProtocolError {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [Kind; 7] = [
+        Kind::Chat,
+        Kind::Join,
+        Kind::Leave,
+        Kind::System,
+        Kind::Error,
+        Kind::Hello,
+        Kind::Direct,
+    ];
+
+    fn sample(kind: Kind) -> Envelope {
+        let mut envelope = Envelope::new(kind, "héllo, 世界").from_sender(42, "alice").in_room("rust");
+        envelope.seq = 7;
+        envelope.timestamp = 1_700_000_000_123;
+        envelope
+    }
+
+    #[test]
+    fn every_kind_round_trips_in_both_formats() {
+        for kind in KINDS {
+            for envelope in [sample(kind), Envelope::new(kind, "")] {
+                for format in [Format::Binary, Format::Json] {
+                    let data = envelope.encode(format);
+                    assert_eq!(Envelope::decode(&data), Ok((envelope.clone(), format)), "{:?} {:?}", kind, format);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn json_uses_lowercase_kind_and_defaults_missing_fields() {
+        for kind in KINDS {
+            let json = String::from_utf8(Envelope::new(kind, "").to_json()).unwrap();
+            let name = format!("{:?}", kind).to_lowercase();
+            assert!(json.contains(&format!("\"kind\":\"{}\"", name)), "{}", json);
+        }
+        let (envelope, format) = Envelope::decode(br#"{"kind":"chat","body":"hi"}"#).unwrap();
+        assert_eq!(format, Format::Json);
+        assert_eq!(envelope, Envelope::chat("hi"));
+    }
+
+    #[test]
+    fn binary_decode_errors() {
+        let data = sample(Kind::Chat).to_binary();
+        assert_eq!(Envelope::decode(&[]), Err(ProtocolError::Empty));
+        assert_eq!(Envelope::from_binary(&data[..BINARY_HEADER_LEN - 1]), Err(ProtocolError::Truncated));
+        // 昵称的长度超出帧的范围
+        assert_eq!(Envelope::from_binary(&data[..BINARY_HEADER_LEN + 3]), Err(ProtocolError::Truncated));
+        // 没有昵称与房间的长度字节
+        assert_eq!(Envelope::from_binary(&data[..BINARY_HEADER_LEN]), Err(ProtocolError::Truncated));
+
+        let mut unknown = data.clone();
+        unknown[0] = 99;
+        assert_eq!(Envelope::decode(&unknown), Err(ProtocolError::UnknownKind(99)));
+
+        let mut body = data.clone();
+        body.push(0xff);
+        assert_eq!(Envelope::from_binary(&body), Err(ProtocolError::InvalidUtf8));
+        let mut nick = data;
+        nick[BINARY_HEADER_LEN + 1] = 0xff;
+        assert_eq!(Envelope::from_binary(&nick), Err(ProtocolError::InvalidUtf8));
+    }
+
+    #[test]
+    fn json_decode_errors() {
+        for data in [&br#"{"kind":"shout","body":"hi"}"#[..], br#"{"kind":"chat""#, br#"{"body":"hi"}"#] {
+            assert!(matches!(Envelope::decode(data), Err(ProtocolError::Json(_))), "{:?}", data);
+        }
+        let mut invalid = br#"{"kind":"chat","body":""#.to_vec();
+        invalid.extend_from_slice(&[0xff, b'"', b'}']);
+        assert!(matches!(Envelope::decode(&invalid), Err(ProtocolError::Json(_))));
+    }
+
+    #[test]
+    fn replay_parses_and_displays() {
+        for replay in [Replay::Last(20), Replay::Since(12345)] {
+            assert_eq!(replay.to_string().parse(), Ok(replay));
+        }
+        assert_eq!(" since  7 ".parse(), Ok(Replay::Since(7)));
+        for invalid in ["", "last", "last ten", "since -1", "first 3", "last 1 2"] {
+            assert!(invalid.parse::<Replay>().is_err(), "{}", invalid);
+        }
+        let hello = Envelope::hello("alice").with_replay(Replay::Last(5));
+        assert_eq!(hello.body, format!("{} last 5", PROTOCOL_VERSION));
+    }
+}
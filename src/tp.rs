@@ -1,98 +1,484 @@
-use std::sync::mpsc::*;
-use std::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
 use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::thread;
+use std::any::Any;
+use std::io;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::time::{Duration, Instant};
 
+mod builder;
+mod channel_queue;
+mod error;
+mod future;
+mod handle;
+mod manual;
+mod queue;
+mod scope;
+mod stats;
+mod timer;
+mod work_stealing;
+
+pub use builder::{ThreadHook, ThreadPoolBuilder};
+pub use error::{SpawnError, ThreadPoolError};
+pub use handle::{JoinHandle, TASK_DROPPED};
+pub use manual::ManualThreadPool;
+pub use queue::{OverflowPolicy, Priority, QueueBackend};
+pub use scope::Scope;
+pub use stats::{Histogram, ThreadPoolStats};
+pub use timer::{TimerDriver, TimerHandle, TimerWheel};
+pub use work_stealing::WorkStealingThreadPool;
+
+use future::Schedule;
+use queue::TaskQueue;
+use stats::Metrics;
 
 pub trait ThreadPool {
-    fn new(threads: u32) -> Result<Self, ()>
+    /// 生成指定线程数量的线程池
+    fn new(threads: u32) -> Result<Self, ThreadPoolError>
+    where
+        Self: Sized,
+    {
+        ThreadPoolBuilder::new().num_threads(threads).build()
+    }
+
+    /// 按照构建器的配置生成线程池
+    fn from_builder(builder: ThreadPoolBuilder) -> Result<Self, ThreadPoolError>
     where
         Self: Sized;
     
     fn spawn<F>(&self, job: F)
     where 
         F: FnOnce() + Send + 'static;
+
+    /// 向线程池中传递带返回值的方法，通过返回的句柄获取结果或 panic 信息
+    fn spawn_with_handle<F, T>(&self, job: F) -> JoinHandle<T>
+    where
+        F: FnOnce() -> T + Send + 'static,
+        T: Send + 'static,
+    {
+        let (handle, completer) = handle::pair();
+        self.spawn(move || {
+            completer.complete(panic::catch_unwind(AssertUnwindSafe(job)));
+        });
+        handle
+    }
+
+    /// 创建作用域，作用域内提交的任务可以借用栈上的数据，
+    /// 返回前等待所有任务执行完毕，任务 panic 时在此重新抛出。
+    /// 不能在本线程池的工作线程中调用，否则可能因线程耗尽而死锁
+    fn scope<'env, F, R>(&'env self, f: F) -> R
+    where
+        Self: Sized,
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env, Self>) -> R,
+    {
+        scope::scope(self, f)
+    }
 }
 
 
 pub enum ThreadPoolMessage {
-    Task(Box<dyn FnOnce() + Send + 'static>),
+    Task(Task),
     Shutdown
 }
 
-// type Task = Box<dyn FnOnce() + Send + 'static>;
+type Task = Box<dyn FnOnce() + Send + 'static>;
 
+/// 未设置最大线程数时，线程池至少可以扩容到的线程数量
 const MAX_THREAD_POOL_SIZE: u32 = 16;
 
+/// 任务 panic 时的回调，参数为工作线程编号与 panic 信息
+pub type PanicHandler = Arc<dyn Fn(u32, &(dyn Any + Send)) + Send + Sync + 'static>;
+
+/// 关闭线程池时如何处理队列中尚未执行的任务
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShutdownMode {
+    /// 执行完队列中的任务后退出，超时后丢弃剩余任务
+    Drain,
+    /// 立即丢弃队列中的任务，只等待正在执行的任务
+    Discard,
+}
+
+/// 关闭线程池的结果
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ShutdownReport {
+    /// 未执行即被丢弃的任务数量
+    pub discarded_tasks: usize,
+    /// 超时后仍在运行、未被 join 的线程数量
+    pub detached_threads: usize,
+    /// 是否在所有线程退出前超时
+    pub timed_out: bool,
+}
+
+/// 共享任务队列的线程池，线程数量在核心线程数与最大线程数之间按负载伸缩
 pub struct SharedQueueThreadPool {
-    // receiver: Arc<Mutex<Receiver<ThreadPoolMessage>>>,
-    sender: Arc<Sender<ThreadPoolMessage>>,
-    pool: Vec<Option<thread::JoinHandle<()>>>,
-    capacity: u32
+    shared: Arc<PoolShared>
+}
+
+/// 所有工作线程共享的状态
+struct PoolShared {
+    queue: Box<dyn TaskQueue>,
+    metrics: Arc<Metrics>,
+    /// 按线程编号保存的线程句柄，线程重建后会被替换
+    workers: Mutex<HashMap<u32, thread::JoinHandle<()>>>,
+    panic_handler: Mutex<PanicHandler>,
+    sizing: Mutex<Sizing>,
+    /// 线程退出时通知等待关闭的线程
+    exited: Condvar,
+    /// 线程池是否已经关闭，保证重复关闭时不做任何事
+    is_shutdown: AtomicBool,
+    /// 超出核心线程数的线程空闲超过该时间后退出
+    keep_alive: Duration,
+    /// 用于创建和重建线程的配置
+    builder: ThreadPoolBuilder
+}
+
+/// 线程数量信息
+struct Sizing {
+    core: u32,
+    max: u32,
+    /// 当前存活的线程数量
+    total: u32,
+    /// 已退出线程释放的编号，优先复用
+    free_ids: Vec<u32>,
+    next_id: u32
+}
+
+impl Sizing {
+    /// 预留一个线程名额并分配线程编号
+    fn reserve(&mut self) -> u32 {
+        self.total += 1;
+        self.free_ids.pop().unwrap_or_else(|| {
+            self.next_id += 1;
+            self.next_id - 1
+        })
+    }
 }
 
 impl ThreadPool for SharedQueueThreadPool {
     /// 生成线程池
-    fn new(threads: u32) -> Result<SharedQueueThreadPool, ()> {
-        if threads > MAX_THREAD_POOL_SIZE {
-            return Err(())
-        }
-        let (sender, receiver) = channel::<ThreadPoolMessage>();
-        let receiver = Arc::new(Mutex::new(receiver));
-        let mut pool = vec![];
-        for id in 0..threads {
-            let recv = Arc::clone(&receiver);
-            let join_handle = thread::spawn(move || {
-                run_task(id, recv);
-            });
-            pool.push(Some(join_handle));
+    fn from_builder(mut builder: ThreadPoolBuilder) -> Result<SharedQueueThreadPool, ThreadPoolError> {
+        builder.validate()?;
+        let (core, max, keep_alive) = builder.sizing();
+        let (backend, capacity, policy) = builder.queue_config();
+        let panic_handler = builder
+            .take_panic_handler()
+            .unwrap_or_else(|| Arc::new(default_panic_handler));
+        let shared = Arc::new(PoolShared {
+            queue: queue::new_queue(backend, capacity, policy),
+            metrics: Arc::new(Metrics::new()),
+            workers: Mutex::new(HashMap::new()),
+            panic_handler: Mutex::new(panic_handler),
+            sizing: Mutex::new(Sizing {
+                core,
+                max,
+                total: 0,
+                free_ids: vec![],
+                next_id: 0
+            }),
+            exited: Condvar::new(),
+            is_shutdown: AtomicBool::new(false),
+            keep_alive,
+            builder
+        });
+        let mut pool = Self { shared };
+        if let Err(err) = pool.shared.grow_to_core() {
+            // 让已经创建的线程退出
+            pool.shutdown();
+            return Err(err.into());
         }
-        Ok(Self {
-            // receiver,
-            sender: Arc::new(sender),
-            capacity: threads,
-            pool: pool
-        })
+        Ok(pool)
     }
     
     
-    /// 向线程池中传递执行方法
+    /// 向线程池中传递执行方法，任务被拒绝时打印错误并丢弃任务
     fn spawn<F>(&self, job: F)
     where F: FnOnce() + Send + 'static {
-        let task = ThreadPoolMessage::Task(Box::new(job));
-        self.sender.send(task).unwrap();
+        if let Err(err) = self.try_spawn(job) {
+            eprintln!("[Error] Task dropped: {}", err);
+        }
     }
     
 }
 
 impl SharedQueueThreadPool {
-    /// 销毁线程池
+    /// 向线程池中传递执行方法，队列已满时按照构建器配置的溢出策略处理
+    pub fn try_spawn<F>(&self, job: F) -> Result<(), SpawnError>
+    where F: FnOnce() + Send + 'static {
+        self.try_spawn_with_priority(Priority::Normal, job)
+    }
+
+    /// 以指定优先级传递执行方法，任务被拒绝时打印错误并丢弃任务
+    pub fn spawn_with_priority<F>(&self, priority: Priority, job: F)
+    where F: FnOnce() + Send + 'static {
+        if let Err(err) = self.try_spawn_with_priority(priority, job) {
+            eprintln!("[Error] Task dropped: {}", err);
+        }
+    }
+
+    /// 以指定优先级传递执行方法，队列已满时按照构建器配置的溢出策略处理
+    pub fn try_spawn_with_priority<F>(&self, priority: Priority, job: F) -> Result<(), SpawnError>
+    where F: FnOnce() + Send + 'static {
+        self.shared.queue.push(priority, Box::new(self.shared.metrics.instrument(job)))?;
+        self.shared.grow_on_load();
+        Ok(())
+    }
+
+    /// 在线程池上运行 Future，Future 被唤醒后重新放入队列等待轮询。
+    /// 首次提交按照溢出策略处理，被唤醒后的重新入队不受队列容量限制
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (handle, first_poll) = future::spawn(&self.shared, future);
+        self.spawn(first_poll);
+        handle
+    }
+
+    /// 设置任务 panic 时的回调，默认打印到标准错误输出
+    pub fn set_panic_handler<H>(&self, handler: H)
+    where H: Fn(u32, &(dyn Any + Send)) + Send + Sync + 'static {
+        *lock(&self.shared.panic_handler) = Arc::new(handler);
+    }
+
+    /// 当前存活的工作线程数量
+    pub fn num_threads(&self) -> u32 {
+        lock(&self.shared.sizing).total
+    }
+
+    /// 线程池运行状态快照
+    pub fn stats(&self) -> ThreadPoolStats {
+        let (queued, _) = self.shared.queue.load();
+        self.shared.metrics.snapshot(queued, self.num_threads() as usize)
+    }
+
+    /// 运行时调整核心线程数与最大线程数，不足核心线程数时立即创建线程，
+    /// 多出的线程在空闲超时后退出
+    pub fn resize(&self, core: u32, max: u32) -> Result<(), ThreadPoolError> {
+        if core == 0 {
+            return Err(ThreadPoolError::NoThreads);
+        }
+        if core > max {
+            return Err(ThreadPoolError::TooManyThreads { requested: core, max });
+        }
+        {
+            let mut sizing = lock(&self.shared.sizing);
+            sizing.core = core;
+            sizing.max = max;
+        }
+        self.shared.grow_to_core()?;
+        Ok(())
+    }
+
+    /// 销毁线程池，执行完队列中的任务并等待所有线程退出
     pub fn shutdown(&mut self) {
-        for _ in 0..self.capacity {
-            self.sender.send(ThreadPoolMessage::Shutdown).unwrap();
+        self.shutdown_until(None, ShutdownMode::Drain);
+    }
+
+    /// 在超时时间内关闭线程池，超时后丢弃剩余任务并分离仍在运行的线程，
+    /// 重复调用时返回空报告
+    pub fn shutdown_graceful(&mut self, timeout: Duration, mode: ShutdownMode) -> ShutdownReport {
+        self.shutdown_until(Some(Instant::now() + timeout), mode)
+    }
+
+    fn shutdown_until(&mut self, deadline: Option<Instant>, mode: ShutdownMode) -> ShutdownReport {
+        let mut report = ShutdownReport::default();
+        if self.shared.is_shutdown.swap(true, Ordering::SeqCst) {
+            return report;
+        }
+        self.shared.queue.close();
+        if mode == ShutdownMode::Discard {
+            report.discarded_tasks += self.shared.queue.drain().len();
         }
 
-        for thread in &mut self.pool {
-            if let Some(thread) = thread.take() {
-                thread.join().unwrap();
+        // 等待所有线程退出，线程在关闭过程中可能因 panic 被重建
+        let mut sizing = lock(&self.shared.sizing);
+        while sizing.total > 0 {
+            match deadline {
+                Some(deadline) => {
+                    let now = Instant::now();
+                    if now >= deadline {
+                        report.timed_out = true;
+                        break;
+                    }
+                    sizing = self
+                        .shared
+                        .exited
+                        .wait_timeout(sizing, deadline - now)
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .0;
+                }
+                None => sizing = self.shared.exited.wait(sizing).unwrap_or_else(PoisonError::into_inner),
             }
         }
+        drop(sizing);
+
+        if report.timed_out {
+            report.discarded_tasks += self.shared.queue.drain().len();
+        }
+        let threads: Vec<_> = lock(&self.shared.workers).drain().collect();
+        for (_, thread) in threads {
+            // 未超时时所有线程都已释放名额，正在退出
+            if !report.timed_out || thread.is_finished() {
+                let _ = thread.join();
+            } else {
+                report.detached_threads += 1;
+            }
+        }
+        report
+    }
+
+}
+
+impl Schedule for PoolShared {
+    fn schedule(&self, task: Task) {
+        let task = Box::new(self.metrics.instrument(task));
+        // 线程池已关闭时丢弃任务，Future 的句柄会收到 TASK_DROPPED
+        let _ = self.queue.push_force(Priority::Normal, task);
+    }
+}
+
+impl PoolShared {
+    /// 创建线程直到达到核心线程数
+    fn grow_to_core(self: &Arc<Self>) -> io::Result<()> {
+        loop {
+            let id = {
+                let mut sizing = lock(&self.sizing);
+                if sizing.total >= sizing.core {
+                    return Ok(());
+                }
+                sizing.reserve()
+            };
+            self.spawn_reserved(id)?;
+        }
+    }
+
+    /// 排队任务多于空闲线程且未达到最大线程数时新建线程
+    fn grow_on_load(self: &Arc<Self>) {
+        let (queued, idle) = self.queue.load();
+        if queued <= idle {
+            return;
+        }
+        let id = {
+            let mut sizing = lock(&self.sizing);
+            if sizing.total >= sizing.max {
+                return;
+            }
+            sizing.reserve()
+        };
+        if let Err(err) = self.spawn_reserved(id) {
+            eprintln!("[Error] Fail to grow thread pool: {}", err);
+        }
+    }
+
+    /// 为已预留的编号创建线程，失败时归还名额
+    fn spawn_reserved(self: &Arc<Self>, id: u32) -> io::Result<()> {
+        spawn_worker(id, self).inspect_err(|_| {
+            lock(&self.sizing).free_ids.push(id);
+            self.release();
+        })
+    }
+
+    /// 空闲超时后判断线程是否应当退出，超出核心线程数时释放名额
+    fn retire(&self, id: u32) -> bool {
+        let mut sizing = lock(&self.sizing);
+        if sizing.total <= sizing.core {
+            return false;
+        }
+        sizing.total -= 1;
+        sizing.free_ids.push(id);
+        // 退出的线程自行分离，不再需要被 join
+        lock(&self.workers).remove(&id);
+        drop(sizing);
+        self.exited.notify_all();
+        true
+    }
+
+    /// 释放一个线程名额并通知等待关闭的线程
+    fn release(&self) {
+        lock(&self.sizing).total -= 1;
+        self.exited.notify_all();
+    }
+}
+
+/// 获取锁，忽略其他线程 panic 导致的中毒状态
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// 从 panic 信息中提取字符串描述
+pub fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(s) = payload.downcast_ref::<&str>() {
+        s
+    } else if let Some(s) = payload.downcast_ref::<String>() {
+        s
+    } else {
+        "Box<dyn Any>"
+    }
+}
+
+fn default_panic_handler(id: u32, payload: &(dyn Any + Send)) {
+    eprintln!("[Error] Thread {} task panicked: {}", id, panic_message(payload));
+}
+
+/// 创建编号为 id 的工作线程并记录其句柄
+fn spawn_worker(id: u32, shared: &Arc<PoolShared>) -> io::Result<()> {
+    let worker_shared = Arc::clone(shared);
+    let join_handle = shared.builder.spawn_thread(id, move || {
+        let sentinel = Sentinel { id, shared: &worker_shared };
+        run_task(id, &worker_shared);
+        sentinel.cancel();
+    })?;
+    lock(&shared.workers).insert(id, join_handle);
+    Ok(())
+}
+
+/// 工作线程因 panic 退出时负责重建线程，保持线程池容量不变
+struct Sentinel<'a> {
+    id: u32,
+    shared: &'a Arc<PoolShared>
+}
+
+impl Sentinel<'_> {
+    /// 线程正常退出，不需要重建
+    fn cancel(self) {
+        std::mem::forget(self);
     }
+}
 
+impl Drop for Sentinel<'_> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            if let Err(err) = self.shared.spawn_reserved(self.id) {
+                eprintln!("[Error] Fail to respawn thread {}: {}", self.id, err);
+            }
+        }
+    }
 }
 
-/// 执行任务方法
-pub fn run_task(id: u32, receiver: Arc<Mutex<Receiver<ThreadPoolMessage>>>) {
+/// 执行任务方法，任务 panic 时交由回调处理，线程继续执行后续任务
+fn run_task(id: u32, shared: &PoolShared) {
     loop {
-        let recv = receiver.lock().unwrap();
-        match recv.recv().unwrap() {
-            ThreadPoolMessage::Task(task) => {
-                drop(recv);
-                task();
+        match shared.queue.pop(shared.keep_alive) {
+            Some(ThreadPoolMessage::Task(task)) => {
+                if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(task)) {
+                    let handler = Arc::clone(&lock(&shared.panic_handler));
+                    handler(id, payload.as_ref());
+                }
             },
 
-            ThreadPoolMessage::Shutdown => {
+            Some(ThreadPoolMessage::Shutdown) => {
+                shared.release();
                 break;
+            },
+
+            None => {
+                if shared.retire(id) {
+                    break;
+                }
             }
         }
     }
@@ -103,4 +489,243 @@ impl Drop for SharedQueueThreadPool {
     fn drop(&mut self) {
         self.shutdown();
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc::{self, Receiver, Sender};
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use super::{
+        panic_message, OverflowPolicy, SharedQueueThreadPool, ShutdownMode, ShutdownReport, SpawnError, Task, ThreadPool,
+        ThreadPoolBuilder, ThreadPoolError,
+    };
+
+    /// 单线程、队列容量为 1 的线程池，唯一的线程被阻塞直到向返回的通道发送消息
+    fn blocked_pool(policy: OverflowPolicy) -> (SharedQueueThreadPool, Sender<()>) {
+        blocked_pool_with_capacity(policy, 1)
+    }
+
+    fn blocked_pool_with_capacity(policy: OverflowPolicy, capacity: usize) -> (SharedQueueThreadPool, Sender<()>) {
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(1)
+            .queue_capacity(capacity)
+            .overflow_policy(policy)
+            .build()
+            .unwrap();
+        let (started, running) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || {
+            started.send(()).unwrap();
+            let _ = blocked.recv();
+        });
+        running.recv().unwrap();
+        (pool, release)
+    }
+
+    /// 记录执行的任务编号
+    fn recorder() -> (Arc<Mutex<Vec<u32>>>, impl Fn(u32) -> Task) {
+        let ran = Arc::new(Mutex::new(vec![]));
+        let record = Arc::clone(&ran);
+        let task = move |n: u32| -> Task {
+            let record = Arc::clone(&record);
+            Box::new(move || record.lock().unwrap().push(n))
+        };
+        (ran, task)
+    }
+
+    fn wait_until(what: &str, mut done: impl FnMut() -> bool) {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !done() {
+            assert!(Instant::now() < deadline, "timed out waiting for {}", what);
+            thread::sleep(Duration::from_millis(5));
+        }
+    }
+
+    #[test]
+    fn reject_when_queue_is_full() {
+        let (mut pool, release) = blocked_pool(OverflowPolicy::Reject);
+        let (ran, task) = recorder();
+        assert_eq!(pool.try_spawn(task(1)), Ok(()));
+        assert_eq!(pool.try_spawn(task(2)), Err(SpawnError::QueueFull));
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![1]);
+        assert_eq!(pool.try_spawn(task(3)), Err(SpawnError::Shutdown));
+    }
+
+    #[test]
+    fn drop_oldest_keeps_newest_task() {
+        let (mut pool, release) = blocked_pool(OverflowPolicy::DropOldest);
+        let (ran, task) = recorder();
+        pool.try_spawn(task(1)).unwrap();
+        pool.try_spawn(task(2)).unwrap();
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![2]);
+    }
+
+    #[test]
+    fn caller_runs_overflowing_task() {
+        let (mut pool, release) = blocked_pool(OverflowPolicy::CallerRuns);
+        let (ran, task) = recorder();
+        pool.try_spawn(task(1)).unwrap();
+        let caller = thread::current().id();
+        let (ran_on, thread_id): (Sender<_>, Receiver<_>) = mpsc::channel();
+        pool.try_spawn(move || ran_on.send(thread::current().id()).unwrap()).unwrap();
+        assert_eq!(thread_id.recv().unwrap(), caller);
+        release.send(()).unwrap();
+        pool.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![1]);
+    }
+
+    #[test]
+    fn resize_grows_and_shrinks() {
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(1)
+            .keep_alive(Duration::from_millis(20))
+            .build()
+            .unwrap();
+        assert_eq!(pool.num_threads(), 1);
+
+        assert!(matches!(pool.resize(0, 4), Err(ThreadPoolError::NoThreads)));
+        assert!(matches!(pool.resize(5, 4), Err(ThreadPoolError::TooManyThreads { requested: 5, max: 4 })));
+
+        pool.resize(3, 4).unwrap();
+        assert_eq!(pool.num_threads(), 3);
+
+        // 超出核心线程数的线程空闲超时后退出
+        pool.resize(1, 4).unwrap();
+        wait_until("idle threads to retire", || pool.num_threads() == 1);
+    }
+
+    #[test]
+    fn grows_to_max_under_load() {
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(3)
+            .build()
+            .unwrap();
+        let (started, running) = mpsc::channel();
+        let (release, blocked) = mpsc::channel::<()>();
+        let blocked = Arc::new(Mutex::new(blocked));
+        for _ in 0..5 {
+            let (started, blocked) = (started.clone(), Arc::clone(&blocked));
+            pool.spawn(move || {
+                started.send(()).unwrap();
+                let _ = blocked.lock().unwrap().recv();
+            });
+        }
+        for _ in 0..3 {
+            running.recv().unwrap();
+        }
+        assert_eq!(pool.num_threads(), 3);
+        drop(release);
+    }
+
+    #[test]
+    fn panicking_task_does_not_kill_worker() {
+        let (panicked, panics) = mpsc::channel();
+        let panicked = Mutex::new(panicked);
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(1)
+            .panic_handler(move |id, payload| {
+                panicked.lock().unwrap().send((id, panic_message(payload).to_owned())).unwrap();
+            })
+            .build()
+            .unwrap();
+        let (ran_on, thread_id) = mpsc::channel();
+        let before = ran_on.clone();
+        pool.spawn(move || before.send(thread::current().id()).unwrap());
+        pool.spawn(|| panic!("task failed"));
+        pool.spawn(move || ran_on.send(thread::current().id()).unwrap());
+
+        assert_eq!(panics.recv().unwrap(), (0, "task failed".to_owned()));
+        assert_eq!(thread_id.recv().unwrap(), thread_id.recv().unwrap());
+        assert_eq!(pool.num_threads(), 1);
+    }
+
+    #[test]
+    fn sentinel_respawns_dead_worker() {
+        // 回调本身 panic 时工作线程退出，由 Sentinel 以相同编号重建
+        let pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(1)
+            .max_threads(1)
+            .panic_handler(|_, _| panic!("handler failed"))
+            .build()
+            .unwrap();
+        let (ran_on, thread_id) = mpsc::channel();
+        let before = ran_on.clone();
+        pool.spawn(move || before.send(thread::current().id()).unwrap());
+        let first = thread_id.recv().unwrap();
+        pool.spawn(|| panic!("task failed"));
+        pool.spawn(move || ran_on.send(thread::current().id()).unwrap());
+
+        assert_ne!(thread_id.recv_timeout(Duration::from_secs(5)).unwrap(), first);
+        assert_eq!(pool.num_threads(), 1);
+        assert_eq!(pool.shared.workers.lock().unwrap().keys().collect::<Vec<_>>(), vec![&0]);
+    }
+
+    /// 单线程、阻塞在一个任务上并排队了 1..=queued 号任务的线程池
+    fn queued_pool(queued: u32) -> (SharedQueueThreadPool, Sender<()>, Arc<Mutex<Vec<u32>>>) {
+        let (pool, release) = blocked_pool_with_capacity(OverflowPolicy::Reject, queued as usize);
+        let (ran, task) = recorder();
+        for n in 1..=queued {
+            pool.try_spawn(task(n)).unwrap();
+        }
+        (pool, release, ran)
+    }
+
+    /// 在另一个线程中延迟放行被阻塞的任务，使关闭操作先开始等待
+    fn release_later(release: Sender<()>, delay: Duration) {
+        thread::spawn(move || {
+            thread::sleep(delay);
+            let _ = release.send(());
+        });
+    }
+
+    #[test]
+    fn graceful_drain_runs_queued_tasks() {
+        let (mut pool, release, ran) = queued_pool(3);
+        release_later(release, Duration::from_millis(20));
+        let report = pool.shutdown_graceful(Duration::from_secs(5), ShutdownMode::Drain);
+        assert_eq!(report, ShutdownReport::default());
+        assert_eq!(*ran.lock().unwrap(), vec![1, 2, 3]);
+        assert_eq!(pool.num_threads(), 0);
+    }
+
+    #[test]
+    fn graceful_discard_drops_queued_tasks() {
+        let (mut pool, release, ran) = queued_pool(3);
+        release_later(release, Duration::from_millis(20));
+        let report = pool.shutdown_graceful(Duration::from_secs(5), ShutdownMode::Discard);
+        assert_eq!(report, ShutdownReport { discarded_tasks: 3, detached_threads: 0, timed_out: false });
+        assert!(ran.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn graceful_timeout_detaches_running_threads() {
+        let (mut pool, release, ran) = queued_pool(2);
+        let report = pool.shutdown_graceful(Duration::from_millis(50), ShutdownMode::Drain);
+        assert_eq!(report, ShutdownReport { discarded_tasks: 2, detached_threads: 1, timed_out: true });
+        release.send(()).unwrap();
+        assert!(ran.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn repeated_shutdown_is_noop() {
+        let (mut pool, release, ran) = queued_pool(1);
+        release.send(()).unwrap();
+        assert_eq!(pool.shutdown_graceful(Duration::from_secs(5), ShutdownMode::Drain), ShutdownReport::default());
+        let report = pool.shutdown_graceful(Duration::from_secs(5), ShutdownMode::Discard);
+        assert_eq!(report, ShutdownReport::default());
+        pool.shutdown();
+        assert_eq!(*ran.lock().unwrap(), vec![1]);
+        assert_eq!(pool.try_spawn(|| {}), Err(SpawnError::Shutdown));
+    }
+}
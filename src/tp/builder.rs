@@ -0,0 +1,237 @@
+use std::any::Any;
+use std::io;
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use crate::affinity;
+
+use super::{OverflowPolicy, QueueBackend, PanicHandler, ThreadPool, ThreadPoolError, MAX_THREAD_POOL_SIZE};
+
+/// 线程启动或退出时的回调，参数为工作线程编号
+pub type ThreadHook = Arc<dyn Fn(u32) + Send + Sync + 'static>;
+
+/// 线程池构建器，用于配置线程数量、线程名、栈大小以及线程启动/退出回调
+#[derive(Clone)]
+pub struct ThreadPoolBuilder {
+    num_threads: u32,
+    /// 未设置时为核心线程数与 `MAX_THREAD_POOL_SIZE` 中的较大者
+    max_threads: Option<u32>,
+    thread_name: Option<String>,
+    stack_size: Option<usize>,
+    on_thread_start: Option<ThreadHook>,
+    on_thread_stop: Option<ThreadHook>,
+    panic_handler: Option<PanicHandler>,
+    queue_capacity: Option<usize>,
+    overflow_policy: OverflowPolicy,
+    queue_backend: QueueBackend,
+    keep_alive: Duration,
+    core_ids: Option<Arc<[usize]>>,
+}
+
+impl Default for ThreadPoolBuilder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ThreadPoolBuilder {
+    /// 默认线程数为 CPU 核数，且不超过 `MAX_THREAD_POOL_SIZE`
+    pub fn new() -> Self {
+        let cpus = thread::available_parallelism().map_or(1, |n| n.get() as u32);
+        Self {
+            num_threads: cpus.min(MAX_THREAD_POOL_SIZE),
+            max_threads: None,
+            thread_name: None,
+            stack_size: None,
+            on_thread_start: None,
+            on_thread_stop: None,
+            panic_handler: None,
+            queue_capacity: None,
+            overflow_policy: OverflowPolicy::Block,
+            queue_backend: QueueBackend::Mutex,
+            keep_alive: Duration::from_secs(60),
+            core_ids: None,
+        }
+    }
+
+    /// 核心工作线程数量，线程池创建时即启动，空闲时也不会退出
+    pub fn num_threads(mut self, threads: u32) -> Self {
+        self.num_threads = threads;
+        self
+    }
+
+    /// 允许的最大线程数量，任务积压时线程池会扩容到该数量。
+    /// 默认为核心线程数与 `MAX_THREAD_POOL_SIZE` 中的较大者
+    pub fn max_threads(mut self, threads: u32) -> Self {
+        self.max_threads = Some(threads);
+        self
+    }
+
+    /// 任务队列的实现方式，默认使用互斥锁保护的队列
+    pub fn queue_backend(mut self, backend: QueueBackend) -> Self {
+        self.queue_backend = backend;
+        self
+    }
+
+    /// 超出核心线程数的线程空闲多久后退出，默认 60 秒
+    pub fn keep_alive(mut self, timeout: Duration) -> Self {
+        self.keep_alive = timeout;
+        self
+    }
+
+    /// 线程名前缀，实际线程名为前缀加线程编号
+    pub fn thread_name<S: Into<String>>(mut self, prefix: S) -> Self {
+        self.thread_name = Some(prefix.into());
+        self
+    }
+
+    /// 工作线程的栈大小（字节）
+    pub fn stack_size(mut self, size: usize) -> Self {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// 工作线程依次绑定的 CPU 核心，线程 i 绑定到 `cores[i % cores.len()]`
+    pub fn core_ids<I: IntoIterator<Item = usize>>(mut self, cores: I) -> Self {
+        let cores: Arc<[usize]> = cores.into_iter().collect();
+        self.core_ids = if cores.is_empty() { None } else { Some(cores) };
+        self
+    }
+
+    /// 工作线程启动后、执行任务前调用
+    pub fn on_thread_start<F>(mut self, hook: F) -> Self
+    where F: Fn(u32) + Send + Sync + 'static {
+        self.on_thread_start = Some(Arc::new(hook));
+        self
+    }
+
+    /// 工作线程退出前调用，线程因 panic 退出时同样会调用
+    pub fn on_thread_stop<F>(mut self, hook: F) -> Self
+    where F: Fn(u32) + Send + Sync + 'static {
+        self.on_thread_stop = Some(Arc::new(hook));
+        self
+    }
+
+    /// 任务 panic 时的回调
+    pub fn panic_handler<F>(mut self, handler: F) -> Self
+    where F: Fn(u32, &(dyn Any + Send)) + Send + Sync + 'static {
+        self.panic_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// 任务队列容量，默认不限制
+    pub fn queue_capacity(mut self, capacity: usize) -> Self {
+        self.queue_capacity = Some(capacity);
+        self
+    }
+
+    /// 有界队列已满时的处理策略，默认阻塞提交者
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// 构建线程池
+    pub fn build<P: ThreadPool>(self) -> Result<P, ThreadPoolError> {
+        P::from_builder(self)
+    }
+
+    pub(crate) fn threads(&self) -> u32 {
+        self.num_threads
+    }
+
+    pub(crate) fn sizing(&self) -> (u32, u32, Duration) {
+        (self.num_threads, self.max(), self.keep_alive)
+    }
+
+    fn max(&self) -> u32 {
+        self.max_threads.unwrap_or(self.num_threads.max(MAX_THREAD_POOL_SIZE))
+    }
+
+    pub(crate) fn queue_config(&self) -> (QueueBackend, Option<usize>, OverflowPolicy) {
+        (self.queue_backend, self.queue_capacity, self.overflow_policy)
+    }
+
+    pub(crate) fn take_panic_handler(&mut self) -> Option<PanicHandler> {
+        self.panic_handler.take()
+    }
+
+    /// 检查配置是否合法
+    pub(crate) fn validate(&self) -> Result<(), ThreadPoolError> {
+        if self.num_threads == 0 {
+            return Err(ThreadPoolError::NoThreads);
+        }
+        if self.num_threads > self.max() {
+            return Err(ThreadPoolError::TooManyThreads {
+                requested: self.num_threads,
+                max: self.max(),
+            });
+        }
+        if self.queue_capacity == Some(0) {
+            return Err(ThreadPoolError::ZeroQueueCapacity);
+        }
+        Ok(())
+    }
+
+    /// 按照配置创建编号为 id 的工作线程
+    pub(crate) fn spawn_thread<F>(&self, id: u32, f: F) -> io::Result<thread::JoinHandle<()>>
+    where F: FnOnce() + Send + 'static {
+        let mut builder = thread::Builder::new();
+        if let Some(prefix) = &self.thread_name {
+            builder = builder.name(format!("{}{}", prefix, id));
+        }
+        if let Some(size) = self.stack_size {
+            builder = builder.stack_size(size);
+        }
+        let on_start = self.on_thread_start.clone();
+        let on_stop = self.on_thread_stop.clone();
+        let core = self.core_ids.as_ref().map(|cores| cores[id as usize % cores.len()]);
+        builder.spawn(move || {
+            if let Some(core) = core {
+                if let Err(err) = affinity::pin_current_thread(core) {
+                    eprintln!("[Error] Fail to pin thread {} to core {}: {}", id, core, err);
+                }
+            }
+            let _stop = StopGuard { id, hook: on_stop };
+            if let Some(hook) = on_start {
+                hook(id);
+            }
+            f();
+        })
+    }
+}
+
+/// 线程退出（包括 panic）时调用退出回调
+struct StopGuard {
+    id: u32,
+    hook: Option<ThreadHook>,
+}
+
+impl Drop for StopGuard {
+    fn drop(&mut self) {
+        if let Some(hook) = &self.hook {
+            hook(self.id);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ThreadPoolBuilder;
+    use crate::tp::ThreadPoolError;
+
+    #[test]
+    fn default_max_threads_follows_num_threads() {
+        let builder = ThreadPoolBuilder::new().num_threads(17);
+        assert!(builder.validate().is_ok());
+        assert_eq!(builder.sizing().1, 17);
+        assert_eq!(ThreadPoolBuilder::new().num_threads(2).sizing().1, 16);
+    }
+
+    #[test]
+    fn explicit_max_threads_is_checked() {
+        let err = ThreadPoolBuilder::new().num_threads(17).max_threads(16).validate().unwrap_err();
+        assert!(matches!(err, ThreadPoolError::TooManyThreads { requested: 17, max: 16 }));
+    }
+}
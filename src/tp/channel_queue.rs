@@ -0,0 +1,262 @@
+use crossbeam::channel::{self, Receiver, Select, Sender, TrySendError};
+
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::queue::{OverflowPolicy, Priority, TaskQueue};
+use super::{SpawnError, Task, ThreadPoolMessage};
+
+/// 队列中的任务，以及它是否占用了一个容量令牌
+type Slot = (Task, bool);
+
+/// 基于 crossbeam 无锁 MPMC 通道的任务队列，每个优先级一条通道，
+/// 有界时通过一条有界的令牌通道限制排队任务数量
+pub(crate) struct ChannelQueue {
+    lanes: Vec<(Sender<Slot>, Receiver<Slot>)>,
+    tokens: Option<(Sender<()>, Receiver<()>)>,
+    /// 关闭时丢弃发送端，让等待中的线程立即醒来
+    close_tx: Mutex<Option<Sender<()>>>,
+    close_rx: Receiver<()>,
+    closed: AtomicBool,
+    idle: AtomicUsize,
+    policy: OverflowPolicy,
+}
+
+impl ChannelQueue {
+    pub(crate) fn new(capacity: Option<usize>, policy: OverflowPolicy) -> Self {
+        let (close_tx, close_rx) = channel::bounded(0);
+        Self {
+            lanes: (0..3).map(|_| channel::unbounded()).collect(),
+            tokens: capacity.map(channel::bounded),
+            close_tx: Mutex::new(Some(close_tx)),
+            close_rx,
+            closed: AtomicBool::new(false),
+            idle: AtomicUsize::new(0),
+            policy,
+        }
+    }
+
+    /// 按优先级从高到低取出一个任务，并归还其占用的令牌
+    fn try_pop(&self) -> Option<Task> {
+        self.lanes.iter().find_map(|(_, rx)| rx.try_recv().ok()).map(|slot| self.release(slot))
+    }
+
+    fn release(&self, (task, token): Slot) -> Task {
+        if let (true, Some((_, tokens))) = (token, &self.tokens) {
+            let _ = tokens.try_recv();
+        }
+        task
+    }
+
+    /// 阻塞直到获得令牌或队列被关闭
+    fn acquire_blocking(&self, tokens: &Sender<()>) -> Result<(), SpawnError> {
+        let mut select = Select::new();
+        let send = select.send(tokens);
+        select.recv(&self.close_rx);
+        let oper = select.select();
+        if oper.index() == send {
+            oper.send(tokens, ()).map_err(|_| SpawnError::Shutdown)
+        } else {
+            let _ = oper.recv(&self.close_rx);
+            Err(SpawnError::Shutdown)
+        }
+    }
+
+    /// 丢弃优先级最低的队列中最早的任务，返回它是否持有令牌
+    fn drop_oldest(&self) -> Option<bool> {
+        let (task, token) = self.lanes.iter().rev().find_map(|(_, rx)| rx.try_recv().ok())?;
+        drop(task);
+        Some(token)
+    }
+}
+
+impl TaskQueue for ChannelQueue {
+    fn push(&self, priority: Priority, task: Task) -> Result<(), SpawnError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(SpawnError::Shutdown);
+        }
+        let tokens = match &self.tokens {
+            Some((tokens, _)) => tokens,
+            None => return self.push_force(priority, task),
+        };
+        loop {
+            match tokens.try_send(()) {
+                Ok(()) => break,
+                Err(TrySendError::Disconnected(_)) => return Err(SpawnError::Shutdown),
+                Err(TrySendError::Full(_)) => match self.policy {
+                    OverflowPolicy::Block => {
+                        self.acquire_blocking(tokens)?;
+                        break;
+                    }
+                    OverflowPolicy::Reject => return Err(SpawnError::QueueFull),
+                    OverflowPolicy::CallerRuns => {
+                        task();
+                        return Ok(());
+                    }
+                    OverflowPolicy::DropOldest => match self.drop_oldest() {
+                        // 沿用被丢弃任务的令牌
+                        Some(true) => break,
+                        Some(false) => continue,
+                        // 令牌被正在出队的任务占用，稍后重试
+                        None => thread::yield_now(),
+                    },
+                },
+            }
+        }
+        self.lanes[priority as usize]
+            .0
+            .send((task, true))
+            .map_err(|_| SpawnError::Shutdown)
+    }
+
+    fn push_force(&self, priority: Priority, task: Task) -> Result<(), SpawnError> {
+        if self.closed.load(Ordering::SeqCst) {
+            return Err(SpawnError::Shutdown);
+        }
+        self.lanes[priority as usize]
+            .0
+            .send((task, false))
+            .map_err(|_| SpawnError::Shutdown)
+    }
+
+    fn close(&self) {
+        self.closed.store(true, Ordering::SeqCst);
+        self.close_tx.lock().unwrap_or_else(PoisonError::into_inner).take();
+    }
+
+    fn drain(&self) -> Vec<Task> {
+        let mut tasks = vec![];
+        for (_, rx) in self.lanes.iter() {
+            while let Ok(slot) = rx.try_recv() {
+                tasks.push(self.release(slot));
+            }
+        }
+        tasks
+    }
+
+    fn load(&self) -> (usize, usize) {
+        let queued = self.lanes.iter().map(|(_, rx)| rx.len()).sum();
+        (queued, self.idle.load(Ordering::SeqCst))
+    }
+
+    fn pop(&self, timeout: Duration) -> Option<ThreadPoolMessage> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            if let Some(task) = self.try_pop() {
+                return Some(ThreadPoolMessage::Task(task));
+            }
+            if self.closed.load(Ordering::SeqCst) {
+                return Some(ThreadPoolMessage::Shutdown);
+            }
+            let mut select = Select::new();
+            for (_, rx) in self.lanes.iter() {
+                select.recv(rx);
+            }
+            select.recv(&self.close_rx);
+            self.idle.fetch_add(1, Ordering::SeqCst);
+            let ready = select.ready_deadline(deadline);
+            self.idle.fetch_sub(1, Ordering::SeqCst);
+            if ready.is_err() {
+                return None;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::sync::{Arc, Mutex};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::ChannelQueue;
+    use crate::tp::queue::{OverflowPolicy, Priority, TaskQueue};
+    use crate::tp::{SpawnError, Task, ThreadPoolMessage};
+
+    /// 取出一个任务并执行，没有任务时 panic
+    fn run_one(queue: &ChannelQueue) {
+        match queue.pop(Duration::ZERO) {
+            Some(ThreadPoolMessage::Task(task)) => task(),
+            _ => panic!("expected a queued task"),
+        }
+    }
+
+    fn recorder() -> (Arc<Mutex<Vec<u32>>>, impl Fn(u32) -> Task) {
+        let ran = Arc::new(Mutex::new(vec![]));
+        let record = Arc::clone(&ran);
+        let task = move |n: u32| -> Task {
+            let record = Arc::clone(&record);
+            Box::new(move || record.lock().unwrap().push(n))
+        };
+        (ran, task)
+    }
+
+    #[test]
+    fn pops_in_fifo_order_within_priority() {
+        let queue = ChannelQueue::new(None, OverflowPolicy::Reject);
+        let (ran, task) = recorder();
+        for n in 1..=3 {
+            queue.push(Priority::Normal, task(n)).unwrap();
+        }
+        queue.push(Priority::High, task(4)).unwrap();
+        assert_eq!(queue.load().0, 4);
+        for _ in 0..4 {
+            run_one(&queue);
+        }
+        assert_eq!(*ran.lock().unwrap(), vec![4, 1, 2, 3]);
+        assert!(queue.pop(Duration::from_millis(1)).is_none());
+    }
+
+    #[test]
+    fn reject_when_full_until_a_task_is_popped() {
+        let queue = ChannelQueue::new(Some(2), OverflowPolicy::Reject);
+        let (ran, task) = recorder();
+        queue.push(Priority::Normal, task(1)).unwrap();
+        queue.push(Priority::Normal, task(2)).unwrap();
+        assert_eq!(queue.push(Priority::Normal, task(3)), Err(SpawnError::QueueFull));
+        // 强制放入的任务不占用容量
+        queue.push_force(Priority::Normal, task(4)).unwrap();
+        run_one(&queue);
+        queue.push(Priority::Normal, task(5)).unwrap();
+        while queue.load().0 > 0 {
+            run_one(&queue);
+        }
+        assert_eq!(*ran.lock().unwrap(), vec![1, 2, 4, 5]);
+    }
+
+    #[test]
+    fn block_waits_for_free_slot() {
+        let queue = Arc::new(ChannelQueue::new(Some(1), OverflowPolicy::Block));
+        let (ran, task) = recorder();
+        queue.push(Priority::Normal, task(1)).unwrap();
+        let (pushed, done) = mpsc::channel();
+        let pusher = {
+            let (queue, task) = (Arc::clone(&queue), task(2));
+            thread::spawn(move || pushed.send(queue.push(Priority::Normal, task)).unwrap())
+        };
+        assert!(done.recv_timeout(Duration::from_millis(50)).is_err());
+        run_one(&queue);
+        assert_eq!(done.recv_timeout(Duration::from_secs(5)).unwrap(), Ok(()));
+        pusher.join().unwrap();
+        run_one(&queue);
+        assert_eq!(*ran.lock().unwrap(), vec![1, 2]);
+    }
+
+    #[test]
+    fn close_wakes_blocked_pusher() {
+        let queue = Arc::new(ChannelQueue::new(Some(1), OverflowPolicy::Block));
+        queue.push(Priority::Normal, Box::new(|| {})).unwrap();
+        let pusher = {
+            let queue = Arc::clone(&queue);
+            thread::spawn(move || queue.push(Priority::Normal, Box::new(|| {})))
+        };
+        thread::sleep(Duration::from_millis(20));
+        queue.close();
+        assert_eq!(pusher.join().unwrap(), Err(SpawnError::Shutdown));
+        assert!(matches!(queue.pop(Duration::ZERO), Some(ThreadPoolMessage::Task(_))));
+        assert!(matches!(queue.pop(Duration::ZERO), Some(ThreadPoolMessage::Shutdown)));
+    }
+}
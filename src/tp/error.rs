@@ -0,0 +1,66 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+/// 线程池创建失败的原因
+#[derive(Debug)]
+pub enum ThreadPoolError {
+    /// 线程数量为 0，线程池无法执行任何任务
+    NoThreads,
+    /// 请求的线程数量超过了允许的最大值
+    TooManyThreads { requested: u32, max: u32 },
+    /// 有界队列的容量为 0
+    ZeroQueueCapacity,
+    /// 操作系统创建线程失败
+    Spawn(io::Error),
+}
+
+impl fmt::Display for ThreadPoolError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ThreadPoolError::NoThreads => write!(f, "thread pool needs at least one thread"),
+            ThreadPoolError::TooManyThreads { requested, max } => write!(
+                f,
+                "requested {} threads but the pool allows at most {}",
+                requested, max
+            ),
+            ThreadPoolError::ZeroQueueCapacity => write!(f, "bounded task queue needs a capacity above zero"),
+            ThreadPoolError::Spawn(err) => write!(f, "failed to spawn worker thread: {}", err),
+        }
+    }
+}
+
+impl Error for ThreadPoolError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ThreadPoolError::Spawn(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for ThreadPoolError {
+    fn from(err: io::Error) -> Self {
+        ThreadPoolError::Spawn(err)
+    }
+}
+
+/// 向线程池提交任务失败的原因
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpawnError {
+    /// 队列已满且溢出策略为拒绝
+    QueueFull,
+    /// 线程池已经关闭
+    Shutdown,
+}
+
+impl fmt::Display for SpawnError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SpawnError::QueueFull => write!(f, "task queue is full"),
+            SpawnError::Shutdown => write!(f, "thread pool has been shut down"),
+        }
+    }
+}
+
+impl Error for SpawnError {}
@@ -0,0 +1,126 @@
+use futures::future::{BoxFuture, FutureExt};
+use futures::task::{waker_ref, ArcWake};
+
+use std::future::Future;
+use std::panic::AssertUnwindSafe;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::Context;
+
+use super::handle::{self, JoinHandle};
+use super::Task;
+
+/// 线程池中用于重新调度被唤醒的 Future 的一端
+pub(crate) trait Schedule: Send + Sync + 'static {
+    /// 放入被唤醒的任务，不受队列容量限制，线程池关闭后直接丢弃
+    fn schedule(&self, task: Task);
+}
+
+/// 在线程池上运行的 Future
+struct FutureTask<S: Schedule> {
+    future: Mutex<Option<BoxFuture<'static, ()>>>,
+    /// 是否已经在队列中等待轮询，避免重复入队
+    scheduled: AtomicBool,
+    scheduler: Arc<S>,
+}
+
+impl<S: Schedule> FutureTask<S> {
+    /// 轮询一次 Future，完成后释放
+    fn run(self: Arc<Self>) {
+        self.scheduled.store(false, Ordering::SeqCst);
+        let mut slot = self.future.lock().unwrap();
+        if let Some(mut future) = slot.take() {
+            let waker = waker_ref(&self);
+            let mut cx = Context::from_waker(&waker);
+            if future.as_mut().poll(&mut cx).is_pending() {
+                *slot = Some(future);
+            }
+        }
+    }
+}
+
+impl<S: Schedule> ArcWake for FutureTask<S> {
+    fn wake_by_ref(arc_self: &Arc<Self>) {
+        if !arc_self.scheduled.swap(true, Ordering::SeqCst) {
+            let task = Arc::clone(arc_self);
+            arc_self.scheduler.schedule(Box::new(move || task.run()));
+        }
+    }
+}
+
+/// 包装 Future，返回其结果句柄与首次轮询的任务，首次轮询由调用者按线程池的正常方式提交
+pub(crate) fn spawn<S, F>(scheduler: &Arc<S>, future: F) -> (JoinHandle<F::Output>, impl FnOnce() + Send + 'static)
+where
+    S: Schedule,
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    let (handle, completer) = handle::pair();
+    let future = async move {
+        completer.complete(AssertUnwindSafe(future).catch_unwind().await);
+    };
+    let task = Arc::new(FutureTask {
+        future: Mutex::new(Some(future.boxed())),
+        scheduled: AtomicBool::new(true),
+        scheduler: Arc::clone(scheduler),
+    });
+    (handle, move || task.run())
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::future::poll_fn;
+
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::task::{Poll, Waker};
+    use std::thread;
+    use std::time::{Duration, Instant};
+
+    use crate::tp::{SharedQueueThreadPool, ThreadPool};
+
+    /// 取出 Future 最近一次挂起时留下的 Waker
+    fn take_waker(slot: &Mutex<Option<Waker>>) -> Waker {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            if let Some(waker) = slot.lock().unwrap().take() {
+                return waker;
+            }
+            assert!(Instant::now() < deadline, "timed out waiting for future to suspend");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn woken_future_is_polled_again() {
+        let pool = SharedQueueThreadPool::new(2).unwrap();
+        let polls = Arc::new(AtomicUsize::new(0));
+        let ready = Arc::new(AtomicBool::new(false));
+        let slot = Arc::new(Mutex::new(None));
+        let handle = {
+            let (polls, ready, slot) = (Arc::clone(&polls), Arc::clone(&ready), Arc::clone(&slot));
+            pool.spawn_future(poll_fn(move |cx| {
+                let n = polls.fetch_add(1, Ordering::SeqCst) + 1;
+                if ready.load(Ordering::SeqCst) {
+                    return Poll::Ready(n);
+                }
+                *slot.lock().unwrap() = Some(cx.waker().clone());
+                Poll::Pending
+            }))
+        };
+
+        // 未被唤醒时不会再次轮询
+        let waker = take_waker(&slot);
+        thread::sleep(Duration::from_millis(20));
+        assert_eq!(polls.load(Ordering::SeqCst), 1);
+        assert!(!handle.is_finished());
+
+        waker.wake();
+        let waker = take_waker(&slot);
+        assert_eq!(polls.load(Ordering::SeqCst), 2);
+
+        ready.store(true, Ordering::SeqCst);
+        waker.wake();
+        assert_eq!(handle.join().unwrap(), 3);
+    }
+}
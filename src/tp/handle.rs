@@ -0,0 +1,196 @@
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+use super::lock;
+
+/// 任务在执行前被丢弃时，`JoinHandle` 返回的错误信息
+pub const TASK_DROPPED: &str = "task dropped before completion";
+
+struct State<T> {
+    result: Option<thread::Result<T>>,
+    waker: Option<Waker>,
+    taken: bool,
+}
+
+/// 任务与 `JoinHandle` 之间共享的结果槽
+struct Packet<T> {
+    state: Mutex<State<T>>,
+    cond: Condvar,
+}
+
+/// 线程池任务的句柄，可以阻塞等待、非阻塞轮询或作为 `Future` 等待任务的返回值，
+/// 任务发生 panic 时返回其 panic 信息
+pub struct JoinHandle<T> {
+    packet: Arc<Packet<T>>,
+}
+
+/// 由任务持有，用于写回执行结果
+pub(crate) struct Completer<T> {
+    packet: Option<Arc<Packet<T>>>,
+}
+
+/// 创建一对互相关联的句柄与结果写入端
+pub(crate) fn pair<T>() -> (JoinHandle<T>, Completer<T>) {
+    let packet = Arc::new(Packet {
+        state: Mutex::new(State {
+            result: None,
+            waker: None,
+            taken: false,
+        }),
+        cond: Condvar::new(),
+    });
+    (
+        JoinHandle { packet: Arc::clone(&packet) },
+        Completer { packet: Some(packet) },
+    )
+}
+
+impl<T> JoinHandle<T> {
+    /// 阻塞直到任务完成并返回结果
+    pub fn join(self) -> thread::Result<T> {
+        let mut state = lock(&self.packet.state);
+        assert!(!state.taken, "JoinHandle result already taken");
+        loop {
+            if let Some(result) = state.result.take() {
+                state.taken = true;
+                return result;
+            }
+            state = self.packet.cond.wait(state).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+
+    /// 非阻塞地获取结果，任务尚未完成或结果已被取走时返回 `None`
+    pub fn try_join(&mut self) -> Option<thread::Result<T>> {
+        let mut state = lock(&self.packet.state);
+        let result = state.result.take();
+        if result.is_some() {
+            state.taken = true;
+        }
+        result
+    }
+
+    /// 任务是否已经完成
+    pub fn is_finished(&self) -> bool {
+        let state = lock(&self.packet.state);
+        state.taken || state.result.is_some()
+    }
+}
+
+impl<T> Future for JoinHandle<T> {
+    type Output = thread::Result<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let mut state = lock(&self.packet.state);
+        assert!(!state.taken, "JoinHandle polled after completion");
+        match state.result.take() {
+            Some(result) => {
+                state.taken = true;
+                Poll::Ready(result)
+            }
+            None => {
+                state.waker = Some(cx.waker().clone());
+                Poll::Pending
+            }
+        }
+    }
+}
+
+impl<T> Completer<T> {
+    /// 写回任务结果并唤醒等待者
+    pub(crate) fn complete(mut self, result: thread::Result<T>) {
+        if let Some(packet) = self.packet.take() {
+            Self::store(&packet, result);
+        }
+    }
+
+    fn store(packet: &Packet<T>, result: thread::Result<T>) {
+        let mut state = lock(&packet.state);
+        state.result = Some(result);
+        let waker = state.waker.take();
+        drop(state);
+        packet.cond.notify_all();
+        if let Some(waker) = waker {
+            waker.wake();
+        }
+    }
+}
+
+impl<T> Drop for Completer<T> {
+    /// 任务未执行便被丢弃时，避免等待者永远阻塞
+    fn drop(&mut self) {
+        if let Some(packet) = self.packet.take() {
+            Self::store(&packet, Err(Box::new(TASK_DROPPED)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    use crate::tp::{panic_message, ManualThreadPool, SharedQueueThreadPool, ThreadPool, TASK_DROPPED};
+
+    #[test]
+    fn join_waits_for_result() {
+        let pool = SharedQueueThreadPool::new(2).unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let handle = pool.spawn_with_handle(move || {
+            rx.recv().unwrap();
+            42
+        });
+        assert!(!handle.is_finished());
+        tx.send(()).unwrap();
+        assert_eq!(handle.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn try_join_only_returns_finished_result_once() {
+        let pool = ManualThreadPool::with_seed(0);
+        let mut handle = pool.spawn_with_handle(|| "done");
+        assert!(handle.try_join().is_none());
+        assert!(!handle.is_finished());
+        pool.run_all();
+        assert!(handle.is_finished());
+        assert_eq!(handle.try_join().unwrap().unwrap(), "done");
+        assert!(handle.try_join().is_none());
+        assert!(handle.is_finished());
+    }
+
+    #[test]
+    fn handle_can_be_awaited() {
+        let pool = SharedQueueThreadPool::new(2).unwrap();
+        let (tx, rx) = mpsc::channel::<()>();
+        let inner = pool.spawn_with_handle(move || {
+            rx.recv().unwrap();
+            7
+        });
+        let outer = pool.spawn_future(async move { inner.await.unwrap() * 6 });
+        // 外层 Future 先挂起，内层任务完成后经 Waker 重新调度
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!outer.is_finished());
+        tx.send(()).unwrap();
+        assert_eq!(outer.join().unwrap(), 42);
+    }
+
+    #[test]
+    fn panic_payload_reaches_handle() {
+        let pool = ManualThreadPool::with_seed(0);
+        let handle = pool.spawn_with_handle(|| -> u32 { panic!("task failed: {}", 3) });
+        pool.run_all();
+        let payload = handle.join().unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "task failed: 3");
+    }
+
+    #[test]
+    fn dropped_pool_reports_task_dropped() {
+        let pool = ManualThreadPool::with_seed(0);
+        let handle = pool.spawn_with_handle(|| 1);
+        drop(pool);
+        let payload = handle.join().unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), TASK_DROPPED);
+    }
+}
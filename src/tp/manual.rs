@@ -0,0 +1,115 @@
+use std::sync::Mutex;
+
+use super::{lock, Task, ThreadPool, ThreadPoolBuilder, ThreadPoolError};
+
+/// 单线程、可复现的线程池，用于测试。任务只在调用 `run_one`/`run_all` 时
+/// 在调用者的线程中执行，每次从待执行任务中按种子决定的伪随机顺序选取一个。
+/// 任务 panic 时直接传播给调用者。任务不会自动执行，因此不能在其上使用 `scope`
+pub struct ManualThreadPool {
+    tasks: Mutex<Vec<Task>>,
+    /// splitmix64 随机数生成器的状态
+    rng: Mutex<u64>,
+}
+
+impl ThreadPool for ManualThreadPool {
+    /// 忽略线程相关的配置，使用种子 0
+    fn from_builder(_builder: ThreadPoolBuilder) -> Result<ManualThreadPool, ThreadPoolError> {
+        Ok(Self::with_seed(0))
+    }
+
+    /// 将任务放入待执行列表
+    fn spawn<F>(&self, job: F)
+    where F: FnOnce() + Send + 'static {
+        lock(&self.tasks).push(Box::new(job));
+    }
+}
+
+impl ManualThreadPool {
+    /// 相同种子与相同提交顺序下，任务的执行顺序相同
+    pub fn with_seed(seed: u64) -> Self {
+        Self {
+            tasks: Mutex::new(vec![]),
+            rng: Mutex::new(seed),
+        }
+    }
+
+    /// 待执行的任务数量
+    pub fn pending(&self) -> usize {
+        lock(&self.tasks).len()
+    }
+
+    /// 执行一个任务，没有待执行的任务时返回 `false`
+    pub fn run_one(&self) -> bool {
+        let task = {
+            let mut tasks = lock(&self.tasks);
+            if tasks.is_empty() {
+                return false;
+            }
+            let index = (self.next_random() % tasks.len() as u64) as usize;
+            tasks.remove(index)
+        };
+        task();
+        true
+    }
+
+    /// 执行任务直到没有待执行的任务，包括执行过程中新提交的任务，返回执行的任务数量
+    pub fn run_all(&self) -> usize {
+        let mut count = 0;
+        while self.run_one() {
+            count += 1;
+        }
+        count
+    }
+
+    fn next_random(&self) -> u64 {
+        let mut state = lock(&self.rng);
+        *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = *state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::ManualThreadPool;
+    use crate::tp::ThreadPool;
+
+    /// 按种子执行 0..8 号任务，返回执行顺序
+    fn order(seed: u64) -> Vec<usize> {
+        let pool = ManualThreadPool::with_seed(seed);
+        let order = Arc::new(Mutex::new(vec![]));
+        for i in 0..8 {
+            let order = Arc::clone(&order);
+            pool.spawn(move || order.lock().unwrap().push(i));
+        }
+        assert_eq!(pool.run_all(), 8);
+        Arc::try_unwrap(order).unwrap().into_inner().unwrap()
+    }
+
+    #[test]
+    fn same_seed_same_order() {
+        assert_eq!(order(7), order(7));
+        let mut sorted = order(7);
+        sorted.sort_unstable();
+        assert_eq!(sorted, (0..8).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn run_all_runs_nested_tasks() {
+        let pool = Arc::new(ManualThreadPool::with_seed(0));
+        let ran = Arc::new(Mutex::new(0));
+        let (inner_pool, inner_ran) = (Arc::clone(&pool), Arc::clone(&ran));
+        pool.spawn(move || {
+            *inner_ran.lock().unwrap() += 1;
+            inner_pool.spawn(move || *inner_ran.lock().unwrap() += 1);
+        });
+        assert_eq!(pool.pending(), 1);
+        assert_eq!(pool.run_all(), 2);
+        assert_eq!(*ran.lock().unwrap(), 2);
+        assert!(!pool.run_one());
+    }
+}
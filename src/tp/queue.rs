@@ -0,0 +1,211 @@
+use std::collections::VecDeque;
+use std::sync::{Condvar, Mutex, MutexGuard, PoisonError};
+use std::time::{Duration, Instant};
+
+use super::channel_queue::ChannelQueue;
+use super::{SpawnError, Task, ThreadPoolMessage};
+
+/// 有界队列已满时的处理策略
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// 阻塞提交者直到队列有空位
+    Block,
+    /// 拒绝新任务并返回 `SpawnError::QueueFull`
+    Reject,
+    /// 丢弃队列中最早的任务，放入新任务
+    DropOldest,
+    /// 在提交者的线程中直接执行新任务
+    CallerRuns,
+}
+
+/// 任务队列的实现方式
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QueueBackend {
+    /// 互斥锁保护的队列，空闲线程在条件变量上等待
+    #[default]
+    Mutex,
+    /// 基于 crossbeam 无锁 MPMC 通道的队列
+    LockFree,
+}
+
+/// 任务优先级，工作线程总是先取高优先级队列中的任务
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+struct State {
+    /// 按优先级从高到低排列的任务队列
+    lanes: [VecDeque<Task>; 3],
+    /// 线程池关闭后不再接收新任务，队列取空后工作线程退出
+    closed: bool,
+    /// 正在等待任务的线程数量
+    idle: usize,
+}
+
+impl State {
+    fn len(&self) -> usize {
+        self.lanes.iter().map(VecDeque::len).sum()
+    }
+}
+
+/// 工作线程共享的任务队列
+pub(crate) trait TaskQueue: Send + Sync {
+    /// 按照溢出策略放入任务，队列已满且策略为 `CallerRuns` 时在当前线程执行，
+    /// 策略为 `DropOldest` 时丢弃优先级最低的队列中最早的任务
+    fn push(&self, priority: Priority, task: Task) -> Result<(), SpawnError>;
+
+    /// 忽略容量限制放入任务，用于重新调度已经被接收的任务，队列关闭后返回错误
+    fn push_force(&self, priority: Priority, task: Task) -> Result<(), SpawnError>;
+
+    /// 关闭队列，之后提交的任务会被拒绝
+    fn close(&self);
+
+    /// 取出所有排队中的任务
+    fn drain(&self) -> Vec<Task>;
+
+    /// 排队中的任务数量与空闲线程数量
+    fn load(&self) -> (usize, usize);
+
+    /// 等待并取出一条消息，队列关闭且为空时返回 `Shutdown`，超时返回 `None`
+    fn pop(&self, timeout: Duration) -> Option<ThreadPoolMessage>;
+}
+
+/// 创建指定实现方式的任务队列，capacity 为 `None` 时不限制容量
+pub(crate) fn new_queue(
+    backend: QueueBackend,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+) -> Box<dyn TaskQueue> {
+    match backend {
+        QueueBackend::Mutex => Box::new(MutexQueue::new(capacity, policy)),
+        QueueBackend::LockFree => Box::new(ChannelQueue::new(capacity, policy)),
+    }
+}
+
+/// 互斥锁保护的任务队列，可选容量上限
+struct MutexQueue {
+    state: Mutex<State>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: Option<usize>,
+    policy: OverflowPolicy,
+}
+
+impl MutexQueue {
+    fn new(capacity: Option<usize>, policy: OverflowPolicy) -> Self {
+        Self {
+            state: Mutex::new(State {
+                lanes: Default::default(),
+                closed: false,
+                idle: 0,
+            }),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity,
+            policy,
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn is_full(&self, state: &State) -> bool {
+        self.capacity.is_some_and(|cap| state.len() >= cap)
+    }
+}
+
+impl TaskQueue for MutexQueue {
+    fn push(&self, priority: Priority, task: Task) -> Result<(), SpawnError> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(SpawnError::Shutdown);
+        }
+        let mut dropped = None;
+        if self.is_full(&state) {
+            match self.policy {
+                OverflowPolicy::Block => {
+                    while self.is_full(&state) && !state.closed {
+                        state = self.not_full.wait(state).unwrap_or_else(PoisonError::into_inner);
+                    }
+                    if state.closed {
+                        return Err(SpawnError::Shutdown);
+                    }
+                }
+                OverflowPolicy::Reject => return Err(SpawnError::QueueFull),
+                OverflowPolicy::DropOldest => {
+                    dropped = state.lanes.iter_mut().rev().find_map(VecDeque::pop_front);
+                }
+                OverflowPolicy::CallerRuns => {
+                    drop(state);
+                    task();
+                    return Ok(());
+                }
+            }
+        }
+        state.lanes[priority as usize].push_back(task);
+        drop(state);
+        self.not_empty.notify_one();
+        // 被丢弃的任务在锁外释放
+        drop(dropped);
+        Ok(())
+    }
+
+    fn push_force(&self, priority: Priority, task: Task) -> Result<(), SpawnError> {
+        let mut state = self.lock();
+        if state.closed {
+            return Err(SpawnError::Shutdown);
+        }
+        state.lanes[priority as usize].push_back(task);
+        drop(state);
+        self.not_empty.notify_one();
+        Ok(())
+    }
+
+    fn close(&self) {
+        self.lock().closed = true;
+        self.not_empty.notify_all();
+        self.not_full.notify_all();
+    }
+
+    fn drain(&self) -> Vec<Task> {
+        let tasks = self.lock().lanes.iter_mut().flat_map(|lane| lane.drain(..)).collect();
+        self.not_full.notify_all();
+        tasks
+    }
+
+    fn load(&self) -> (usize, usize) {
+        let state = self.lock();
+        (state.len(), state.idle)
+    }
+
+    fn pop(&self, timeout: Duration) -> Option<ThreadPoolMessage> {
+        let deadline = Instant::now() + timeout;
+        let mut state = self.lock();
+        loop {
+            if let Some(task) = state.lanes.iter_mut().find_map(VecDeque::pop_front) {
+                drop(state);
+                self.not_full.notify_one();
+                return Some(ThreadPoolMessage::Task(task));
+            }
+            if state.closed {
+                return Some(ThreadPoolMessage::Shutdown);
+            }
+            let now = Instant::now();
+            if now >= deadline {
+                return None;
+            }
+            state.idle += 1;
+            state = self
+                .not_empty
+                .wait_timeout(state, deadline - now)
+                .unwrap_or_else(PoisonError::into_inner)
+                .0;
+            state.idle -= 1;
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::any::Any;
+use std::marker::PhantomData;
+use std::mem;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use super::{lock, ThreadPool, TASK_DROPPED};
+
+type ScopedTask<'scope> = Box<dyn FnOnce() + Send + 'scope>;
+
+/// 作用域内所有任务共享的状态
+struct ScopeState {
+    /// 尚未结束的任务数量
+    pending: Mutex<usize>,
+    done: Condvar,
+    /// 第一个 panic 的任务的 panic 信息
+    panic: Mutex<Option<Box<dyn Any + Send>>>,
+}
+
+impl ScopeState {
+    fn record_panic(&self, payload: Box<dyn Any + Send>) {
+        lock(&self.panic).get_or_insert(payload);
+    }
+
+    fn finish(&self) {
+        let mut pending = lock(&self.pending);
+        *pending -= 1;
+        if *pending == 0 {
+            self.done.notify_all();
+        }
+    }
+
+    fn wait(&self) {
+        let mut pending = lock(&self.pending);
+        while *pending > 0 {
+            pending = self.done.wait(pending).unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// 线程池上的作用域，通过 `ThreadPool::scope` 创建
+pub struct Scope<'scope, 'env: 'scope, P> {
+    pool: &'scope P,
+    state: Arc<ScopeState>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, P: ThreadPool> Scope<'scope, '_, P> {
+    /// 提交可以借用作用域外数据的任务
+    pub fn spawn<F>(&'scope self, job: F)
+    where F: FnOnce() + Send + 'scope {
+        *lock(&self.state.pending) += 1;
+        let job: ScopedTask<'scope> = Box::new(job);
+        // SAFETY: 只延长了任务的生命周期参数。`scope` 返回或 unwind 之前会等待
+        // pending 归零，而 `ScopedJob` 在任务执行完或被丢弃（先释放任务再 `finish`）
+        // 时才减少计数，因此任务借用的 'scope 数据在任务存活期间始终有效
+        let job: ScopedTask<'static> = unsafe { mem::transmute(job) };
+        let job = ScopedJob {
+            job: Some(job),
+            state: Arc::clone(&self.state),
+        };
+        self.pool.spawn(move || job.run());
+    }
+}
+
+/// 包装作用域内的任务，无论任务执行还是被线程池丢弃都会通知作用域
+struct ScopedJob {
+    job: Option<ScopedTask<'static>>,
+    state: Arc<ScopeState>,
+}
+
+impl ScopedJob {
+    fn run(mut self) {
+        if let Some(job) = self.job.take() {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                self.state.record_panic(payload);
+            }
+        }
+    }
+}
+
+impl Drop for ScopedJob {
+    fn drop(&mut self) {
+        // 先释放任务（及其借用），再通知作用域
+        if let Some(job) = self.job.take() {
+            drop(job);
+            self.state.record_panic(Box::new(TASK_DROPPED));
+        }
+        self.state.finish();
+    }
+}
+
+pub(crate) fn scope<'env, P, F, R>(pool: &'env P, f: F) -> R
+where
+    P: ThreadPool,
+    F: for<'scope> FnOnce(&'scope Scope<'scope, 'env, P>) -> R,
+{
+    let scope = Scope {
+        pool,
+        state: Arc::new(ScopeState {
+            pending: Mutex::new(0),
+            done: Condvar::new(),
+            panic: Mutex::new(None),
+        }),
+        scope: PhantomData,
+        env: PhantomData,
+    };
+    let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+    scope.state.wait();
+    let result = match result {
+        Ok(result) => result,
+        Err(payload) => panic::resume_unwind(payload),
+    };
+    let payload = lock(&scope.state.panic).take();
+    if let Some(payload) = payload {
+        panic::resume_unwind(payload);
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+    use std::time::Duration;
+
+    use crate::tp::{panic_message, SharedQueueThreadPool, ThreadPool};
+
+    #[test]
+    fn panicking_task_joins_before_scope_returns() {
+        let pool = SharedQueueThreadPool::new(2).unwrap();
+        let finished = AtomicBool::new(false);
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.scope(|scope| {
+                scope.spawn(|| panic!("scoped task failed"));
+                scope.spawn(|| {
+                    thread::sleep(Duration::from_millis(50));
+                    finished.store(true, Ordering::SeqCst);
+                });
+            })
+        }));
+        let payload = result.unwrap_err();
+        assert_eq!(panic_message(payload.as_ref()), "scoped task failed");
+        assert!(finished.load(Ordering::SeqCst));
+    }
+}
@@ -0,0 +1,277 @@
+use std::fmt;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// 直方图桶的数量，第 i 个桶统计耗时小于 2^i 微秒的样本，最后一个桶收纳更长的耗时
+const BUCKETS: usize = 32;
+
+/// 按 2 的幂次划分的耗时直方图快照
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Histogram {
+    buckets: [u64; BUCKETS],
+    count: u64,
+    sum_micros: u64,
+    max_micros: u64,
+}
+
+impl Histogram {
+    /// 样本数量
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+
+    /// 平均耗时
+    pub fn mean(&self) -> Duration {
+        match self.count {
+            0 => Duration::ZERO,
+            n => Duration::from_micros(self.sum_micros / n),
+        }
+    }
+
+    /// 最大耗时
+    pub fn max(&self) -> Duration {
+        Duration::from_micros(self.max_micros)
+    }
+
+    /// 分位数 `q`（0.0 到 1.0）所在桶的上界，最后一个桶返回最大耗时
+    pub fn percentile(&self, q: f64) -> Duration {
+        if self.count == 0 {
+            return Duration::ZERO;
+        }
+        let target = ((self.count as f64 * q.clamp(0.0, 1.0)).ceil() as u64).max(1);
+        let mut seen = 0;
+        for (i, n) in self.buckets.iter().enumerate() {
+            seen += n;
+            if seen >= target {
+                return bucket_bound(i).min(self.max());
+            }
+        }
+        self.max()
+    }
+
+    /// 非空桶的上界与样本数量
+    pub fn buckets(&self) -> impl Iterator<Item = (Duration, u64)> + '_ {
+        self.buckets
+            .iter()
+            .enumerate()
+            .filter(|(_, n)| **n > 0)
+            .map(|(i, n)| (bucket_bound(i), *n))
+    }
+}
+
+impl fmt::Display for Histogram {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "n={} mean={:?} p50={:?} p99={:?} max={:?}",
+            self.count,
+            self.mean(),
+            self.percentile(0.5),
+            self.percentile(0.99),
+            self.max()
+        )
+    }
+}
+
+fn bucket_bound(i: usize) -> Duration {
+    if i == BUCKETS - 1 {
+        Duration::MAX
+    } else {
+        Duration::from_micros(1 << i)
+    }
+}
+
+/// 可并发记录的直方图
+struct AtomicHistogram {
+    buckets: [AtomicU64; BUCKETS],
+    count: AtomicU64,
+    sum_micros: AtomicU64,
+    max_micros: AtomicU64,
+}
+
+impl AtomicHistogram {
+    fn new() -> Self {
+        Self {
+            buckets: Default::default(),
+            count: AtomicU64::new(0),
+            sum_micros: AtomicU64::new(0),
+            max_micros: AtomicU64::new(0),
+        }
+    }
+
+    fn record(&self, elapsed: Duration) {
+        let micros = elapsed.as_micros().min(u64::MAX as u128) as u64;
+        // 小于 2^i 微秒的样本落在第 i 个桶
+        let index = (64 - micros.leading_zeros() as usize).min(BUCKETS - 1);
+        self.buckets[index].fetch_add(1, Ordering::Relaxed);
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_micros.fetch_add(micros, Ordering::Relaxed);
+        self.max_micros.fetch_max(micros, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Histogram {
+        let mut buckets = [0; BUCKETS];
+        for (dst, src) in buckets.iter_mut().zip(self.buckets.iter()) {
+            *dst = src.load(Ordering::Relaxed);
+        }
+        Histogram {
+            buckets,
+            count: self.count.load(Ordering::Relaxed),
+            sum_micros: self.sum_micros.load(Ordering::Relaxed),
+            max_micros: self.max_micros.load(Ordering::Relaxed),
+        }
+    }
+}
+
+/// 线程池运行状态快照
+#[derive(Debug, Clone)]
+pub struct ThreadPoolStats {
+    /// 排队等待执行的任务数量
+    pub queued_tasks: usize,
+    /// 正在执行任务的线程数量
+    pub active_workers: usize,
+    /// 存活的线程数量
+    pub total_workers: usize,
+    /// 正常完成的任务数量
+    pub completed_tasks: u64,
+    /// 发生 panic 的任务数量
+    pub panicked_tasks: u64,
+    /// 任务从提交到开始执行的等待时间
+    pub queue_wait: Histogram,
+    /// 任务的执行时间
+    pub execution_time: Histogram,
+}
+
+impl fmt::Display for ThreadPoolStats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "workers {}/{} busy, {} queued, {} completed, {} panicked",
+            self.active_workers,
+            self.total_workers,
+            self.queued_tasks,
+            self.completed_tasks,
+            self.panicked_tasks
+        )?;
+        writeln!(f, "queue wait: {}", self.queue_wait)?;
+        write!(f, "execution:  {}", self.execution_time)
+    }
+}
+
+/// 线程池内部的计数器
+pub(crate) struct Metrics {
+    active: AtomicUsize,
+    completed: AtomicU64,
+    panicked: AtomicU64,
+    queue_wait: AtomicHistogram,
+    execution_time: AtomicHistogram,
+}
+
+impl Metrics {
+    pub(crate) fn new() -> Self {
+        Self {
+            active: AtomicUsize::new(0),
+            completed: AtomicU64::new(0),
+            panicked: AtomicU64::new(0),
+            queue_wait: AtomicHistogram::new(),
+            execution_time: AtomicHistogram::new(),
+        }
+    }
+
+    /// 包装任务，在任务开始时记录等待时间，结束（包括 panic）时记录执行时间
+    pub(crate) fn instrument<F>(self: &Arc<Self>, job: F) -> impl FnOnce() + Send + 'static
+    where F: FnOnce() + Send + 'static {
+        let metrics = Arc::clone(self);
+        let enqueued = Instant::now();
+        move || {
+            let started = Instant::now();
+            metrics.queue_wait.record(started - enqueued);
+            metrics.active.fetch_add(1, Ordering::Relaxed);
+            let _guard = RunGuard { metrics: &metrics, started };
+            job();
+        }
+    }
+
+    pub(crate) fn snapshot(&self, queued_tasks: usize, total_workers: usize) -> ThreadPoolStats {
+        ThreadPoolStats {
+            queued_tasks,
+            active_workers: self.active.load(Ordering::Relaxed),
+            total_workers,
+            completed_tasks: self.completed.load(Ordering::Relaxed),
+            panicked_tasks: self.panicked.load(Ordering::Relaxed),
+            queue_wait: self.queue_wait.snapshot(),
+            execution_time: self.execution_time.snapshot(),
+        }
+    }
+}
+
+/// 任务结束时记录结果，panic 展开时同样会执行
+struct RunGuard<'a> {
+    metrics: &'a Metrics,
+    started: Instant,
+}
+
+impl Drop for RunGuard<'_> {
+    fn drop(&mut self) {
+        let metrics = self.metrics;
+        metrics.execution_time.record(self.started.elapsed());
+        if thread::panicking() {
+            metrics.panicked.fetch_add(1, Ordering::Relaxed);
+        } else {
+            metrics.completed.fetch_add(1, Ordering::Relaxed);
+        }
+        metrics.active.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::AtomicHistogram;
+    use crate::tp::{SharedQueueThreadPool, ThreadPool, ThreadPoolBuilder};
+
+    #[test]
+    fn counters_follow_spawned_tasks() {
+        let mut pool: SharedQueueThreadPool = ThreadPoolBuilder::new()
+            .num_threads(2)
+            .panic_handler(|_, _| {})
+            .build()
+            .unwrap();
+        let stats = pool.stats();
+        assert_eq!((stats.completed_tasks, stats.panicked_tasks, stats.queue_wait.count()), (0, 0, 0));
+
+        for _ in 0..3 {
+            pool.spawn(|| {});
+        }
+        pool.spawn(|| panic!("task failed"));
+        pool.shutdown();
+
+        let stats = pool.stats();
+        assert_eq!(stats.completed_tasks, 3);
+        assert_eq!(stats.panicked_tasks, 1);
+        assert_eq!(stats.active_workers, 0);
+        assert_eq!(stats.queued_tasks, 0);
+        assert_eq!(stats.queue_wait.count(), 4);
+        assert_eq!(stats.execution_time.count(), 4);
+    }
+
+    #[test]
+    fn histogram_buckets_by_power_of_two() {
+        let histogram = AtomicHistogram::new();
+        for micros in [0, 1, 3, 3, 1000] {
+            histogram.record(Duration::from_micros(micros));
+        }
+        let snapshot = histogram.snapshot();
+        assert_eq!(snapshot.count(), 5);
+        assert_eq!(snapshot.max(), Duration::from_micros(1000));
+        assert_eq!(snapshot.mean(), Duration::from_micros(201));
+        let buckets: Vec<_> = snapshot.buckets().map(|(bound, n)| (bound.as_micros(), n)).collect();
+        assert_eq!(buckets, vec![(1, 1), (2, 1), (4, 2), (1024, 1)]);
+        assert_eq!(snapshot.percentile(0.5), Duration::from_micros(4));
+        // 最后一个样本所在桶的上界被截断为最大耗时
+        assert_eq!(snapshot.percentile(1.0), Duration::from_micros(1000));
+    }
+}
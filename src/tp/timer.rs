@@ -0,0 +1,340 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use super::ThreadPool;
+
+/// 定时器到期后执行的回调
+enum Callback {
+    Once(Box<dyn FnOnce() + Send + 'static>),
+    Every {
+        callback: Arc<dyn Fn() + Send + Sync + 'static>,
+        period_ticks: u64,
+    },
+}
+
+struct Entry {
+    /// 到期的时间片编号
+    deadline: u64,
+    callback: Callback,
+    cancelled: Arc<AtomicBool>,
+}
+
+/// 时间轮，每个槽保存到期时间片对槽数取模后落在该槽的定时器
+struct Wheel {
+    slots: Vec<Vec<Entry>>,
+    /// 已经处理过的时间片编号
+    current: u64,
+    /// 时间轮中的定时器数量，包括已取消但尚未清理的
+    pending: usize,
+    /// 最早的到期时间片，可能属于已取消的定时器，没有定时器时为 `u64::MAX`
+    earliest: u64,
+}
+
+/// 定时器句柄，用于取消定时器
+#[derive(Clone)]
+pub struct TimerHandle {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl TimerHandle {
+    /// 取消定时器，已经开始执行的回调不受影响，周期定时器不再触发
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/// 哈希时间轮，到期的回调提交到线程池中执行。
+/// 可以由 `start` 创建的后台线程驱动，也可以在 epoll/mio 事件循环中
+/// 以 `next_timeout` 作为等待超时时间，并在每次唤醒后调用 `advance`
+pub struct TimerWheel<P: ThreadPool> {
+    pool: Arc<P>,
+    wheel: Mutex<Wheel>,
+    tick: Duration,
+    start: Instant,
+}
+
+impl<P: ThreadPool> TimerWheel<P> {
+    /// 创建时间片长度为 tick、共 slots 个槽的时间轮
+    pub fn new(pool: Arc<P>, tick: Duration, slots: usize) -> Self {
+        assert!(tick > Duration::ZERO, "timer tick must be positive");
+        assert!(slots > 0, "timer wheel needs at least one slot");
+        Self {
+            pool,
+            wheel: Mutex::new(Wheel {
+                slots: (0..slots).map(|_| vec![]).collect(),
+                current: 0,
+                pending: 0,
+                earliest: u64::MAX,
+            }),
+            tick,
+            start: Instant::now(),
+        }
+    }
+
+    fn lock(&self) -> MutexGuard<'_, Wheel> {
+        self.wheel.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// 将时长换算为时间片数量，向上取整且至少为 1
+    fn ticks(&self, delay: Duration) -> u64 {
+        let tick = self.tick.as_nanos();
+        (delay.as_nanos().div_ceil(tick).max(1)).min(u64::MAX as u128) as u64
+    }
+
+    /// 当前时刻所在的时间片编号
+    fn tick_at(&self, now: Instant) -> u64 {
+        (now.saturating_duration_since(self.start).as_nanos() / self.tick.as_nanos()) as u64
+    }
+
+    fn insert(&self, delay: Duration, callback: Callback) -> TimerHandle {
+        let cancelled = Arc::new(AtomicBool::new(false));
+        let mut wheel = self.lock();
+        // 事件循环可能没有及时推进时间轮，到期时间按当前时刻计算
+        let deadline = (self.tick_at(Instant::now()) + self.ticks(delay)).max(wheel.current + 1);
+        wheel.push(Entry {
+            deadline,
+            callback,
+            cancelled: Arc::clone(&cancelled),
+        });
+        TimerHandle { cancelled }
+    }
+
+    /// delay 之后执行一次回调
+    pub fn schedule_after<F>(&self, delay: Duration, callback: F) -> TimerHandle
+    where F: FnOnce() + Send + 'static {
+        self.insert(delay, Callback::Once(Box::new(callback)))
+    }
+
+    /// 每隔 period 执行一次回调，直到被取消
+    pub fn schedule_every<F>(&self, period: Duration, callback: F) -> TimerHandle
+    where F: Fn() + Send + Sync + 'static {
+        let period_ticks = self.ticks(period);
+        self.insert(
+            period,
+            Callback::Every {
+                callback: Arc::new(callback),
+                period_ticks,
+            },
+        )
+    }
+
+    /// 距离最早的定时器到期的时长，时间轮中没有定时器时返回 `None`。
+    /// 等待期间由其他线程加入的更早的定时器不会缩短这次等待
+    pub fn next_timeout(&self, now: Instant) -> Option<Duration> {
+        let wheel = self.lock();
+        if wheel.pending == 0 {
+            return None;
+        }
+        let next = self.tick.as_nanos().saturating_mul(wheel.earliest.max(wheel.current + 1) as u128);
+        let next = self.start.checked_add(Duration::from_nanos(next.min(u64::MAX as u128) as u64));
+        Some(next.map_or(Duration::MAX, |next| next.saturating_duration_since(now)))
+    }
+
+    /// 推进时间轮到 now，将到期的回调提交到线程池，返回触发的定时器数量
+    pub fn advance(&self, now: Instant) -> usize {
+        let target = self.tick_at(now);
+        let mut due = vec![];
+        {
+            let mut wheel = self.lock();
+            if wheel.current >= target {
+                return 0;
+            }
+            // 没有到期的定时器时直接跳到目标时间片，否则每个槽最多检查一次
+            if wheel.earliest <= target {
+                let slots = wheel.slots.len() as u64;
+                let first = (wheel.current + 1).max(target.saturating_sub(slots - 1));
+                for tick in first..=target {
+                    let slot = (tick % slots) as usize;
+                    let entries = std::mem::take(&mut wheel.slots[slot]);
+                    for entry in entries {
+                        if entry.cancelled.load(Ordering::SeqCst) {
+                            wheel.pending -= 1;
+                        } else if entry.deadline > target {
+                            wheel.slots[slot].push(entry);
+                        } else {
+                            wheel.pending -= 1;
+                            due.push(entry);
+                        }
+                    }
+                }
+                let earliest = wheel.slots.iter().flatten().map(|entry| entry.deadline).min();
+                wheel.earliest = earliest.unwrap_or(u64::MAX);
+            }
+            wheel.current = target;
+            // 周期定时器重新放入时间轮
+            for entry in due.iter() {
+                if let Callback::Every { callback, period_ticks } = &entry.callback {
+                    let deadline = entry.deadline.max(wheel.current) + period_ticks;
+                    wheel.push(Entry {
+                        deadline,
+                        callback: Callback::Every {
+                            callback: Arc::clone(callback),
+                            period_ticks: *period_ticks,
+                        },
+                        cancelled: Arc::clone(&entry.cancelled),
+                    });
+                }
+            }
+        }
+
+        let fired = due.len();
+        // 回调提交后、执行前仍可能被取消，执行时再检查一次
+        for entry in due {
+            let cancelled = entry.cancelled;
+            match entry.callback {
+                Callback::Once(callback) => self.pool.spawn(move || {
+                    if !cancelled.load(Ordering::SeqCst) {
+                        callback();
+                    }
+                }),
+                Callback::Every { callback, .. } => self.pool.spawn(move || {
+                    if !cancelled.load(Ordering::SeqCst) {
+                        callback();
+                    }
+                }),
+            }
+        }
+        fired
+    }
+}
+
+impl<P: ThreadPool + Send + Sync + 'static> TimerWheel<P> {
+    /// 创建后台线程按时间片推进时间轮，返回的驱动器被丢弃时线程退出
+    pub fn start(self: &Arc<Self>) -> TimerDriver {
+        let stop = Arc::new(AtomicBool::new(false));
+        let wheel = Arc::clone(self);
+        let thread_stop = Arc::clone(&stop);
+        let thread = thread::Builder::new()
+            .name("timer-wheel".into())
+            .spawn(move || {
+                while !thread_stop.load(Ordering::SeqCst) {
+                    let timeout = wheel.next_timeout(Instant::now()).unwrap_or(wheel.tick);
+                    thread::sleep(timeout.min(wheel.tick));
+                    wheel.advance(Instant::now());
+                }
+            })
+            .expect("Fail to spawn timer thread");
+        TimerDriver {
+            stop,
+            thread: Some(thread),
+        }
+    }
+}
+
+impl Wheel {
+    fn push(&mut self, entry: Entry) {
+        let slot = (entry.deadline % self.slots.len() as u64) as usize;
+        self.earliest = self.earliest.min(entry.deadline);
+        self.slots[slot].push(entry);
+        self.pending += 1;
+    }
+}
+
+/// 驱动时间轮的后台线程
+pub struct TimerDriver {
+    stop: Arc<AtomicBool>,
+    thread: Option<thread::JoinHandle<()>>,
+}
+
+impl Drop for TimerDriver {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::Arc;
+    use std::time::{Duration, Instant};
+
+    use super::TimerWheel;
+    use crate::tp::ManualThreadPool;
+
+    const TICK: Duration = Duration::from_millis(10);
+
+    #[test]
+    fn once_timer_cancelled_after_dispatch_does_not_fire() {
+        let pool = Arc::new(ManualThreadPool::with_seed(0));
+        let timers = TimerWheel::new(Arc::clone(&pool), TICK, 8);
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fired);
+        let handle = timers.schedule_after(TICK, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        assert_eq!(timers.advance(Instant::now() + TICK * 3), 1);
+        assert_eq!(pool.pending(), 1);
+        handle.cancel();
+        pool.run_all();
+        assert_eq!(fired.load(Ordering::SeqCst), 0);
+    }
+
+    #[test]
+    fn every_timer_fires_until_cancelled() {
+        let pool = Arc::new(ManualThreadPool::with_seed(0));
+        let timers = TimerWheel::new(Arc::clone(&pool), TICK, 8);
+        let fired = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&fired);
+        let handle = timers.schedule_every(TICK * 2, move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let now = Instant::now();
+        timers.advance(now + TICK * 3);
+        pool.run_all();
+        timers.advance(now + TICK * 6);
+        pool.run_all();
+        assert_eq!(fired.load(Ordering::SeqCst), 2);
+
+        handle.cancel();
+        timers.advance(now + TICK * 20);
+        pool.run_all();
+        assert_eq!(fired.load(Ordering::SeqCst), 2);
+        assert_eq!(timers.next_timeout(now), None);
+    }
+
+    #[test]
+    fn advance_jumps_over_idle_ticks() {
+        let pool = Arc::new(ManualThreadPool::with_seed(0));
+        let tick = Duration::from_secs(1);
+        let timers = TimerWheel::new(Arc::clone(&pool), tick, 8);
+        let start = timers.start;
+        timers.schedule_after(tick * 1000, || {});
+
+        assert_eq!(timers.advance(start + tick * 500), 0);
+        assert_eq!(timers.lock().current, 500);
+        assert_eq!(timers.next_timeout(start + tick * 500), Some(tick * 500));
+        assert_eq!(timers.advance(start + tick * 1000), 1);
+        assert_eq!(pool.run_all(), 1);
+        assert_eq!(timers.next_timeout(start), None);
+    }
+
+    #[test]
+    fn next_timeout_follows_earliest_deadline() {
+        let pool = Arc::new(ManualThreadPool::with_seed(0));
+        let tick = Duration::from_secs(1);
+        let timers = TimerWheel::new(Arc::clone(&pool), tick, 8);
+        let start = timers.start;
+        timers.schedule_after(tick * 30, || {});
+        timers.schedule_after(tick * 9, || {});
+        timers.schedule_after(tick * 2, || {});
+        assert_eq!(timers.next_timeout(start), Some(tick * 2));
+
+        // 跨越整圈时每个槽都被检查，到期时间不晚于目标的定时器全部触发
+        assert_eq!(timers.advance(start + tick * 20), 2);
+        assert_eq!(timers.next_timeout(start + tick * 20), Some(tick * 10));
+        assert_eq!(timers.advance(start + tick * 29), 0);
+        assert_eq!(timers.advance(start + tick * 30), 1);
+        assert_eq!(timers.next_timeout(start + tick * 30), None);
+    }
+}
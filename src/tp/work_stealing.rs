@@ -0,0 +1,287 @@
+use crossbeam::deque::{Injector, Steal, Stealer, Worker};
+
+use std::cell::RefCell;
+use std::future::Future;
+use std::iter;
+use std::panic::{self, AssertUnwindSafe};
+use std::rc::Rc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+use std::thread;
+
+use super::future::{self, Schedule};
+use super::stats::Metrics;
+use super::{
+    default_panic_handler, lock, panic_message, JoinHandle, PanicHandler, SpawnError, ThreadPool, ThreadPoolBuilder,
+    ThreadPoolError, ThreadPoolStats,
+};
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// 工作线程所属线程池及其本地队列
+type LocalQueue = Option<(*const Shared, Rc<Worker<Job>>)>;
+
+/// 所有工作线程共享的状态
+struct Shared {
+    /// 全局注入队列，线程池外部提交的任务放在这里
+    injector: Injector<Job>,
+    /// 每个工作线程本地队列的窃取端
+    stealers: Vec<Stealer<Job>>,
+    metrics: Arc<Metrics>,
+    /// 任务 panic 时的回调
+    panic_handler: PanicHandler,
+    /// 是否已经关闭，只在持有 `sleep_lock` 时修改
+    shutdown: AtomicBool,
+    /// 空闲线程在此等待新任务，提交任务与关闭线程池也在持有它时进行
+    sleep_lock: Mutex<()>,
+    sleep_cond: Condvar,
+}
+
+thread_local! {
+    /// 当前线程若为工作线程，则保存其所属线程池与本地队列
+    static LOCAL: RefCell<LocalQueue> = RefCell::new(None);
+}
+
+/// 基于工作窃取的线程池，每个工作线程拥有自己的双端队列，
+/// 本地队列为空时从全局队列或其他线程的队列中窃取任务。
+/// 目前只在 `tp_bench` 中与 `SharedQueueThreadPool` 对比，服务器的各个后端都使用 `SharedQueueThreadPool`
+pub struct WorkStealingThreadPool {
+    shared: Arc<Shared>,
+    pool: Vec<Option<thread::JoinHandle<()>>>,
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    /// 生成线程池
+    fn from_builder(mut builder: ThreadPoolBuilder) -> Result<WorkStealingThreadPool, ThreadPoolError> {
+        builder.validate()?;
+        let panic_handler = builder
+            .take_panic_handler()
+            .unwrap_or_else(|| Arc::new(default_panic_handler));
+        let threads = builder.threads();
+        let workers: Vec<Worker<Job>> = (0..threads).map(|_| Worker::new_fifo()).collect();
+        let shared = Arc::new(Shared {
+            injector: Injector::new(),
+            stealers: workers.iter().map(Worker::stealer).collect(),
+            metrics: Arc::new(Metrics::new()),
+            panic_handler,
+            shutdown: AtomicBool::new(false),
+            sleep_lock: Mutex::new(()),
+            sleep_cond: Condvar::new(),
+        });
+        let mut pool = Self { shared, pool: vec![] };
+        for (id, local) in workers.into_iter().enumerate() {
+            let shared = Arc::clone(&pool.shared);
+            match builder.spawn_thread(id as u32, move || run_worker(id as u32, shared, local)) {
+                Ok(join_handle) => pool.pool.push(Some(join_handle)),
+                Err(err) => {
+                    // 让已经创建的线程退出
+                    pool.shutdown();
+                    return Err(err.into());
+                }
+            }
+        }
+        Ok(pool)
+    }
+
+    /// 向线程池中传递执行方法，线程池关闭后打印错误并丢弃任务
+    fn spawn<F>(&self, job: F)
+    where F: FnOnce() + Send + 'static {
+        if let Err(err) = self.try_spawn(job) {
+            eprintln!("[Error] Task dropped: {}", err);
+        }
+    }
+}
+
+impl WorkStealingThreadPool {
+    /// 向线程池中传递执行方法，工作线程内提交的任务优先放入本地队列
+    pub fn try_spawn<F>(&self, job: F) -> Result<(), SpawnError>
+    where F: FnOnce() + Send + 'static {
+        self.shared.push(Box::new(self.shared.metrics.instrument(job)))
+    }
+
+    /// 在线程池上运行 Future，Future 被唤醒后重新放入队列等待轮询
+    pub fn spawn_future<F>(&self, future: F) -> JoinHandle<F::Output>
+    where
+        F: Future + Send + 'static,
+        F::Output: Send + 'static,
+    {
+        let (handle, first_poll) = future::spawn(&self.shared, future);
+        self.spawn(first_poll);
+        handle
+    }
+
+    /// 线程池运行状态快照
+    pub fn stats(&self) -> ThreadPoolStats {
+        let queued = self.shared.injector.len()
+            + self.shared.stealers.iter().map(Stealer::len).sum::<usize>();
+        let workers = self.pool.iter().filter(|t| t.is_some()).count();
+        self.shared.metrics.snapshot(queued, workers)
+    }
+
+    /// 销毁线程池，已提交的任务会在线程退出前执行完，之后提交的任务会被拒绝
+    pub fn shutdown(&mut self) {
+        let guard = lock(&self.shared.sleep_lock);
+        self.shared.shutdown.store(true, Ordering::SeqCst);
+        drop(guard);
+        self.shared.sleep_cond.notify_all();
+
+        for (id, thread) in self.pool.iter_mut().enumerate() {
+            if let Some(thread) = thread.take() {
+                // 在 Drop 中调用，不能因为线程 panic 而再次 panic
+                if let Err(payload) = thread.join() {
+                    eprintln!("[Error] Thread {} panicked: {}", id, panic_message(payload.as_ref()));
+                }
+            }
+        }
+    }
+}
+
+impl Schedule for Shared {
+    /// 线程池关闭后 Future 被丢弃，句柄得到 `TASK_DROPPED`
+    fn schedule(&self, job: Job) {
+        let _ = self.push(Box::new(self.metrics.instrument(job)));
+    }
+}
+
+impl Shared {
+    /// 放入任务并唤醒一个空闲线程，本线程池的工作线程提交的任务放入它的本地队列。
+    /// 持有等待锁时检查是否关闭并放入任务，工作线程退出前同样持有它检查队列，
+    /// 所以被接收的任务一定会被执行，也不会在检查队列与进入等待之间丢失唤醒
+    fn push(&self, job: Job) -> Result<(), SpawnError> {
+        let guard = lock(&self.sleep_lock);
+        if self.shutdown.load(Ordering::SeqCst) {
+            return Err(SpawnError::Shutdown);
+        }
+        let job = LOCAL.with(|local| match &*local.borrow() {
+            Some((owner, worker)) if std::ptr::eq(*owner, self) => {
+                worker.push(job);
+                None
+            }
+            _ => Some(job),
+        });
+        if let Some(job) = job {
+            self.injector.push(job);
+        }
+        drop(guard);
+        self.sleep_cond.notify_one();
+        Ok(())
+    }
+
+    /// 依次从本地队列、全局队列、其他线程的队列中获取任务
+    fn find_task(&self, local: &Worker<Job>) -> Option<Job> {
+        local.pop().or_else(|| {
+            iter::repeat_with(|| {
+                self.injector
+                    .steal_batch_and_pop(local)
+                    .or_else(|| self.stealers.iter().map(Stealer::steal).collect())
+            })
+            .find(|s| !s.is_retry())
+            .and_then(Steal::success)
+        })
+    }
+
+    fn has_pending(&self) -> bool {
+        !self.injector.is_empty() || self.stealers.iter().any(|s| !s.is_empty())
+    }
+}
+
+/// 工作线程主循环，任务 panic 时交由回调处理，线程继续执行后续任务
+fn run_worker(id: u32, shared: Arc<Shared>, local: Worker<Job>) {
+    let local = Rc::new(local);
+    LOCAL.with(|l| *l.borrow_mut() = Some((Arc::as_ptr(&shared), Rc::clone(&local))));
+    loop {
+        if let Some(task) = shared.find_task(&local) {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(task)) {
+                (shared.panic_handler)(id, payload.as_ref());
+            }
+            continue;
+        }
+
+        let guard = lock(&shared.sleep_lock);
+        if shared.has_pending() {
+            continue;
+        }
+        if shared.shutdown.load(Ordering::SeqCst) {
+            break;
+        }
+        drop(shared.sleep_cond.wait(guard).unwrap_or_else(PoisonError::into_inner));
+    }
+    LOCAL.with(|l| *l.borrow_mut() = None);
+    println!("[Debug] Thread {} exit", id);
+}
+
+impl Drop for WorkStealingThreadPool {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+    use std::thread;
+    use std::time::Duration;
+
+    use super::WorkStealingThreadPool;
+    use crate::tp::{SpawnError, ThreadPool};
+
+    #[test]
+    fn worker_pushes_to_local_queue() {
+        let pool = WorkStealingThreadPool::new(1).unwrap();
+        let shared = Arc::clone(&pool.shared);
+        let (tx, rx) = mpsc::channel();
+        pool.spawn(move || {
+            shared.push(Box::new(|| {})).unwrap();
+            shared.push(Box::new(|| {})).unwrap();
+            tx.send((shared.stealers[0].len(), shared.injector.len())).unwrap();
+        });
+        assert_eq!(rx.recv_timeout(Duration::from_secs(5)).unwrap(), (2, 0));
+    }
+
+    #[test]
+    fn idle_worker_steals_from_busy_one() {
+        let pool = WorkStealingThreadPool::new(2).unwrap();
+        let shared = Arc::clone(&pool.shared);
+        let (tx, rx) = mpsc::channel();
+        pool.spawn(move || {
+            let (stolen_tx, stolen_rx) = mpsc::channel();
+            shared.push(Box::new(move || stolen_tx.send(thread::current().id()).unwrap())).unwrap();
+            // 本线程一直占用，本地队列中的任务只能被另一个线程窃取
+            let thief = stolen_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+            tx.send(thief != thread::current().id()).unwrap();
+        });
+        assert!(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+
+    #[test]
+    fn shutdown_drains_queued_tasks() {
+        let mut pool = WorkStealingThreadPool::new(2).unwrap();
+        let ran = Arc::new(AtomicUsize::new(0));
+        for _ in 0..100 {
+            let ran = Arc::clone(&ran);
+            pool.spawn(move || {
+                thread::sleep(Duration::from_millis(1));
+                ran.fetch_add(1, Ordering::SeqCst);
+            });
+        }
+        pool.shutdown();
+        assert_eq!(ran.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn spawn_after_shutdown_is_rejected() {
+        let mut pool = WorkStealingThreadPool::new(2).unwrap();
+        pool.shutdown();
+        let ran = Arc::new(AtomicUsize::new(0));
+        let task_ran = Arc::clone(&ran);
+        let err = pool.try_spawn(move || {
+            task_ran.fetch_add(1, Ordering::SeqCst);
+        });
+        assert_eq!(err, Err(SpawnError::Shutdown));
+        // 被拒绝的任务随闭包一起释放
+        assert_eq!(Arc::strong_count(&ran), 1);
+        assert!(!pool.shared.has_pending());
+        assert_eq!(ran.load(Ordering::SeqCst), 0);
+    }
+}